@@ -0,0 +1,234 @@
+use crate::expressions::Expr;
+use crate::lexer::Token;
+use crate::statements::Stmt;
+
+use std::fmt;
+
+// Where two programs first differ. The path leads from the program down to the node, e.g.
+// `program[2].body[0].initializer.right`, and the reason tells what is different there
+#[derive(Debug)]
+pub struct Difference {
+    pub path: String,
+    pub reason: String,
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "program{}: {}", self.path, self.reason)
+    }
+}
+
+type Compared = Result<(), Difference>;
+
+// Compares what the two programs are made of, not how they were written: positions, whitespace and the way number
+// literals are spelled (`1.50` and `1.5`) don't count. Parentheses do, they are a grouping node in the tree
+pub fn compare_programs(a: &[Stmt], b: &[Stmt]) -> Compared {
+    list(a, b, statement)
+}
+
+fn differ(reason: String) -> Compared {
+    Err(Difference { path: String::new(), reason })
+}
+
+fn field(name: &str, compared: Compared) -> Compared {
+    compared.map_err(|mut difference| {
+        difference.path.insert_str(0, &format!(".{}", name));
+        difference
+    })
+}
+
+fn list<T>(a: &[T], b: &[T], compare: fn(&T, &T) -> Compared) -> Compared {
+    for (i, (a, b)) in a.iter().zip(b).enumerate() {
+        compare(a, b).map_err(|mut difference| {
+            difference.path.insert_str(0, &format!("[{}]", i));
+            difference
+        })?;
+    }
+    if a.len() != b.len() {
+        return differ(format!("{} against {} elements", a.len(), b.len()));
+    }
+    Ok(())
+}
+
+fn optional<T>(a: Option<&T>, b: Option<&T>, compare: fn(&T, &T) -> Compared) -> Compared {
+    match (a, b) {
+        (Some(a), Some(b)) => compare(a, b),
+        (None, None) => Ok(()),
+        (Some(_), None) => differ(String::from("present against missing")),
+        (None, Some(_)) => differ(String::from("missing against present")),
+    }
+}
+
+fn token(a: &Token, b: &Token) -> Compared {
+    if a.value != b.value {
+        return differ(format!("'{}' against '{}'", a.value, b.value));
+    }
+    Ok(())
+}
+
+fn expression(a: &Expr, b: &Expr) -> Compared {
+    match (a, b) {
+        (Expr::ArrayLiteral { elements: a, .. }, Expr::ArrayLiteral { elements: b, .. })
+        | (Expr::Tuple { elements: a, .. }, Expr::Tuple { elements: b, .. }) => {
+            field("elements", list(a, b, expression))
+        }
+        (Expr::Assign { name: name_a, value: value_a }, Expr::Assign { name: name_b, value: value_b }) => {
+            field("name", token(name_a, name_b))?;
+            field("value", expression(value_a, value_b))
+        }
+        (
+            Expr::Binary { left: left_a, operator: operator_a, right: right_a },
+            Expr::Binary { left: left_b, operator: operator_b, right: right_b },
+        )
+        | (
+            Expr::Logical { left: left_a, operator: operator_a, right: right_a },
+            Expr::Logical { left: left_b, operator: operator_b, right: right_b },
+        ) => {
+            field("left", expression(left_a, left_b))?;
+            field("operator", token(operator_a, operator_b))?;
+            field("right", expression(right_a, right_b))
+        }
+        (
+            Expr::Call { callee: callee_a, arguments: arguments_a, .. },
+            Expr::Call { callee: callee_b, arguments: arguments_b, .. },
+        ) => {
+            field("callee", expression(callee_a, callee_b))?;
+            field("arguments", list(arguments_a, arguments_b, expression))
+        }
+        (Expr::Grouping { expression: a }, Expr::Grouping { expression: b }) => field("expression", expression(a, b)),
+        (Expr::Interpolation { parts: a, .. }, Expr::Interpolation { parts: b, .. }) => {
+            field("parts", list(a, b, expression))
+        }
+        (
+            Expr::Index { target: target_a, index: index_a, .. },
+            Expr::Index { target: target_b, index: index_b, .. },
+        ) => {
+            field("target", expression(target_a, target_b))?;
+            field("index", expression(index_a, index_b))
+        }
+        (
+            Expr::IndexAssign { target: target_a, index: index_a, value: value_a, .. },
+            Expr::IndexAssign { target: target_b, index: index_b, value: value_b, .. },
+        ) => {
+            field("target", expression(target_a, target_b))?;
+            field("index", expression(index_a, index_b))?;
+            field("value", expression(value_a, value_b))
+        }
+        (Expr::Lambda { params: params_a, body: body_a }, Expr::Lambda { params: params_b, body: body_b }) => {
+            field("params", list(params_a, params_b, token))?;
+            field("body", list(body_a, body_b, statement))
+        }
+        (Expr::Literal { value: a, .. }, Expr::Literal { value: b, .. }) => {
+            if a != b {
+                return field("value", differ(format!("{} against {}", a.repr(), b.repr())));
+            }
+            Ok(())
+        }
+        (Expr::MapLiteral { entries: a, .. }, Expr::MapLiteral { entries: b, .. }) => {
+            field("entries", list(a, b, |(key_a, value_a), (key_b, value_b)| {
+                field("key", expression(key_a, key_b))?;
+                field("value", expression(value_a, value_b))
+            }))
+        }
+        (
+            Expr::Ternary { condition: condition_a, then_branch: then_a, else_branch: else_a, .. },
+            Expr::Ternary { condition: condition_b, then_branch: then_b, else_branch: else_b, .. },
+        ) => {
+            field("condition", expression(condition_a, condition_b))?;
+            field("then_branch", expression(then_a, then_b))?;
+            field("else_branch", expression(else_a, else_b))
+        }
+        (
+            Expr::Unary { operator: operator_a, right: right_a },
+            Expr::Unary { operator: operator_b, right: right_b },
+        ) => {
+            field("operator", token(operator_a, operator_b))?;
+            field("right", expression(right_a, right_b))
+        }
+        (Expr::Variable { name: a }, Expr::Variable { name: b }) => field("name", token(a, b)),
+        _ => differ(format!("{} against {}", expression_kind(a), expression_kind(b))),
+    }
+}
+
+// The keywords of if, while and return are only there for positions. A for loop is a while with its keyword
+// being `for`, so the two only differ if their conditions, bodies or increments do
+fn statement(a: &Stmt, b: &Stmt) -> Compared {
+    match (a, b) {
+        (Stmt::Block { statements: a }, Stmt::Block { statements: b }) => field("statements", list(a, b, statement)),
+        (Stmt::Break { .. }, Stmt::Break { .. }) | (Stmt::Continue { .. }, Stmt::Continue { .. }) => Ok(()),
+        (Stmt::Expression { expression: a }, Stmt::Expression { expression: b }) => {
+            field("expression", expression(a, b))
+        }
+        (
+            Stmt::Function { name: name_a, params: params_a, body: body_a },
+            Stmt::Function { name: name_b, params: params_b, body: body_b },
+        ) => {
+            field("name", token(name_a, name_b))?;
+            field("params", list(params_a, params_b, token))?;
+            field("body", list(body_a, body_b, statement))
+        }
+        (
+            Stmt::If { condition: condition_a, then_branch: then_a, else_branch: else_a, .. },
+            Stmt::If { condition: condition_b, then_branch: then_b, else_branch: else_b, .. },
+        ) => {
+            field("condition", expression(condition_a, condition_b))?;
+            field("then_branch", statement(then_a, then_b))?;
+            field("else_branch", optional(else_a.as_deref(), else_b.as_deref(), statement))
+        }
+        (Stmt::Print { expressions: a, .. }, Stmt::Print { expressions: b, .. }) => {
+            field("expressions", list(a, b, expression))
+        }
+        (Stmt::Return { value: a, .. }, Stmt::Return { value: b, .. }) => {
+            field("value", optional(a.as_ref(), b.as_ref(), expression))
+        }
+        (Stmt::Var { name: name_a, initializer: init_a }, Stmt::Var { name: name_b, initializer: init_b }) => {
+            field("name", token(name_a, name_b))?;
+            field("initializer", expression(init_a, init_b))
+        }
+        (
+            Stmt::While { condition: condition_a, body: body_a, increment: increment_a, .. },
+            Stmt::While { condition: condition_b, body: body_b, increment: increment_b, .. },
+        ) => {
+            field("condition", expression(condition_a, condition_b))?;
+            field("body", statement(body_a, body_b))?;
+            field("increment", optional(increment_a.as_deref(), increment_b.as_deref(), expression))
+        }
+        _ => differ(format!("{} against {}", statement_kind(a), statement_kind(b))),
+    }
+}
+
+fn expression_kind(expression: &Expr) -> &'static str {
+    match expression {
+        Expr::ArrayLiteral { .. } => "array",
+        Expr::Assign { .. } => "assignment",
+        Expr::Binary { .. } => "binary expression",
+        Expr::Call { .. } => "call",
+        Expr::Grouping { .. } => "grouping",
+        Expr::Interpolation { .. } => "interpolation",
+        Expr::Index { .. } => "index",
+        Expr::IndexAssign { .. } => "index assignment",
+        Expr::Tuple { .. } => "tuple",
+        Expr::Lambda { .. } => "lambda",
+        Expr::Literal { .. } => "literal",
+        Expr::MapLiteral { .. } => "map",
+        Expr::Logical { .. } => "logical expression",
+        Expr::Ternary { .. } => "ternary",
+        Expr::Unary { .. } => "unary expression",
+        Expr::Variable { .. } => "variable",
+    }
+}
+
+fn statement_kind(statement: &Stmt) -> &'static str {
+    match statement {
+        Stmt::Block { .. } => "block",
+        Stmt::Break { .. } => "break",
+        Stmt::Continue { .. } => "continue",
+        Stmt::Expression { .. } => "expression statement",
+        Stmt::Function { .. } => "function declaration",
+        Stmt::If { .. } => "if",
+        Stmt::Print { .. } => "print",
+        Stmt::Return { .. } => "return",
+        Stmt::Var { .. } => "variable declaration",
+        Stmt::While { .. } => "loop",
+    }
+}
@@ -0,0 +1,234 @@
+use crate::expressions::{format_number, Expr, ExprVisitor, LiteralValue};
+use crate::lexer::Token;
+use crate::statements::{Stmt, StmtVisitor};
+
+// Writes the AST as JSON for tools outside of wolff. Every node is an object whose "type" is the name of its
+// variant, followed by its fields in the order they are declared in, so the same program always gives the same text.
+// Tokens keep their position, as {"value": ..., "line": ..., "col": ...}
+pub struct AstJson;
+
+impl AstJson {
+    pub fn program(&mut self, statements: &[Stmt]) -> String {
+        self.statements(statements)
+    }
+
+    fn node(kind: &str, fields: &[(&str, String)]) -> String {
+        let mut output = format!("{{\"type\":{}", json_string(kind));
+        for (name, value) in fields {
+            output.push_str(&format!(",{}:{}", json_string(name), value));
+        }
+        output.push('}');
+        output
+    }
+
+    fn token(token: &Token) -> String {
+        format!("{{\"value\":{},\"line\":{},\"col\":{}}}", json_string(&token.value), token.line, token.col)
+    }
+
+    fn tokens(tokens: &[Token]) -> String {
+        list(tokens.iter().map(Self::token))
+    }
+
+    fn expressions(&mut self, expressions: &[Expr]) -> String {
+        list(expressions.iter().map(|expression| expression.accept(self)))
+    }
+
+    fn statements(&mut self, statements: &[Stmt]) -> String {
+        list(statements.iter().map(|statement| statement.accept(self)))
+    }
+}
+
+fn list(items: impl Iterator<Item = String>) -> String {
+    format!("[{}]", items.collect::<Vec<String>>().join(","))
+}
+
+fn json_string(text: &str) -> String {
+    let mut output = String::from("\"");
+    for ch in text.chars() {
+        match ch {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            '\n' => output.push_str("\\n"),
+            '\r' => output.push_str("\\r"),
+            '\t' => output.push_str("\\t"),
+            ch if (ch as u32) < 0x20 => output.push_str(&format!("\\u{:04x}", ch as u32)),
+            ch => output.push(ch),
+        }
+    }
+    output.push('"');
+    output
+}
+
+// Only the literals the parser makes can show up in the AST. JSON has no infinities or NaN, a number literal too
+// big for a float becomes null, its token still has the digits
+fn json_value(value: &LiteralValue) -> String {
+    match value {
+        LiteralValue::Number(number) if number.is_finite() => format_number(*number),
+        LiteralValue::Number(_) | LiteralValue::Nil => String::from("null"),
+        LiteralValue::Text(text) => json_string(text),
+        LiteralValue::Bytes(bytes) => list(bytes.iter().map(u8::to_string)),
+        LiteralValue::Bool(val) => val.to_string(),
+        _ => json_string(&value.to_string()),
+    }
+}
+
+fn literal_kind(value: &LiteralValue) -> &'static str {
+    match value {
+        LiteralValue::Number(_) => "Number",
+        LiteralValue::Text(_) => "Text",
+        LiteralValue::Bytes(_) => "Bytes",
+        LiteralValue::Bool(_) => "Bool",
+        LiteralValue::Function(_) | LiteralValue::NativeFunction(_) => "Function",
+        LiteralValue::Tuple(_) => "Tuple",
+        LiteralValue::Array(_) => "Array",
+        LiteralValue::Map(_) => "Map",
+        LiteralValue::Nil => "Nil",
+    }
+}
+
+impl ExprVisitor<String> for AstJson {
+    fn visit_array_literal_expr(&mut self, bracket: &Token, elements: &[Expr]) -> String {
+        Self::node("ArrayLiteral", &[("bracket", Self::token(bracket)), ("elements", self.expressions(elements))])
+    }
+
+    fn visit_assign_expr(&mut self, name: &Token, value: &Expr) -> String {
+        Self::node("Assign", &[("name", Self::token(name)), ("value", value.accept(self))])
+    }
+
+    fn visit_binary_expr(&mut self, left: &Expr, operator: &Token, right: &Expr) -> String {
+        let fields = [("left", left.accept(self)), ("operator", Self::token(operator)), ("right", right.accept(self))];
+        Self::node("Binary", &fields)
+    }
+
+    fn visit_call_expr(&mut self, callee: &Expr, paren: &Token, arguments: &[Expr]) -> String {
+        let fields = [
+            ("callee", callee.accept(self)),
+            ("paren", Self::token(paren)),
+            ("arguments", self.expressions(arguments)),
+        ];
+        Self::node("Call", &fields)
+    }
+
+    fn visit_grouping_expr(&mut self, expression: &Expr) -> String {
+        Self::node("Grouping", &[("expression", expression.accept(self))])
+    }
+
+    fn visit_interpolation_expr(&mut self, string: &Token, parts: &[Expr]) -> String {
+        Self::node("Interpolation", &[("string", Self::token(string)), ("parts", self.expressions(parts))])
+    }
+
+    fn visit_lambda_expr(&mut self, params: &[Token], body: &[Stmt]) -> String {
+        Self::node("Lambda", &[("params", Self::tokens(params)), ("body", self.statements(body))])
+    }
+
+    fn visit_literal_expr(&mut self, value: &LiteralValue, token: Option<&Token>) -> String {
+        let value = Self::node(literal_kind(value), &[("value", json_value(value))]);
+        let token = token.map_or(String::from("null"), Self::token);
+        Self::node("Literal", &[("value", value), ("token", token)])
+    }
+
+    fn visit_map_literal_expr(&mut self, brace: &Token, entries: &[(Expr, Expr)]) -> String {
+        // Pairs, keys can be any expression so an object wouldn't do
+        let entries = entries.iter().map(|(key, value)| format!("[{},{}]", key.accept(self), value.accept(self)));
+        let entries = list(entries);
+        Self::node("MapLiteral", &[("brace", Self::token(brace)), ("entries", entries)])
+    }
+
+    fn visit_logical_expr(&mut self, left: &Expr, operator: &Token, right: &Expr) -> String {
+        let fields = [("left", left.accept(self)), ("operator", Self::token(operator)), ("right", right.accept(self))];
+        Self::node("Logical", &fields)
+    }
+
+    fn visit_index_expr(&mut self, target: &Expr, bracket: &Token, index: &Expr) -> String {
+        let fields = [("target", target.accept(self)), ("bracket", Self::token(bracket)), ("index", index.accept(self))];
+        Self::node("Index", &fields)
+    }
+
+    fn visit_index_assign_expr(&mut self, target: &Expr, bracket: &Token, index: &Expr, value: &Expr) -> String {
+        let fields = [
+            ("target", target.accept(self)),
+            ("bracket", Self::token(bracket)),
+            ("index", index.accept(self)),
+            ("value", value.accept(self)),
+        ];
+        Self::node("IndexAssign", &fields)
+    }
+
+    fn visit_tuple_expr(&mut self, paren: &Token, elements: &[Expr]) -> String {
+        Self::node("Tuple", &[("paren", Self::token(paren)), ("elements", self.expressions(elements))])
+    }
+
+    fn visit_ternary_expr(&mut self, condition: &Expr, question: &Token, then_branch: &Expr, else_branch: &Expr) -> String {
+        let fields = [
+            ("condition", condition.accept(self)),
+            ("question", Self::token(question)),
+            ("then_branch", then_branch.accept(self)),
+            ("else_branch", else_branch.accept(self)),
+        ];
+        Self::node("Ternary", &fields)
+    }
+
+    fn visit_unary_expr(&mut self, operator: &Token, right: &Expr) -> String {
+        Self::node("Unary", &[("operator", Self::token(operator)), ("right", right.accept(self))])
+    }
+
+    fn visit_variable_expr(&mut self, name: &Token) -> String {
+        Self::node("Variable", &[("name", Self::token(name))])
+    }
+}
+
+impl StmtVisitor<String> for AstJson {
+    fn visit_block_stmt(&mut self, statements: &[Stmt]) -> String {
+        Self::node("Block", &[("statements", self.statements(statements))])
+    }
+
+    fn visit_break_stmt(&mut self, keyword: &Token) -> String {
+        Self::node("Break", &[("keyword", Self::token(keyword))])
+    }
+
+    fn visit_continue_stmt(&mut self, keyword: &Token) -> String {
+        Self::node("Continue", &[("keyword", Self::token(keyword))])
+    }
+
+    fn visit_expression_stmt(&mut self, expression: &Expr) -> String {
+        Self::node("Expression", &[("expression", expression.accept(self))])
+    }
+
+    fn visit_function_stmt(&mut self, name: &Token, params: &[Token], body: &[Stmt]) -> String {
+        let fields = [("name", Self::token(name)), ("params", Self::tokens(params)), ("body", self.statements(body))];
+        Self::node("Function", &fields)
+    }
+
+    fn visit_if_stmt(&mut self, keyword: &Token, condition: &Expr, then_branch: &Stmt, else_branch: Option<&Stmt>) -> String {
+        let fields = [
+            ("keyword", Self::token(keyword)),
+            ("condition", condition.accept(self)),
+            ("then_branch", then_branch.accept(self)),
+            ("else_branch", else_branch.map_or(String::from("null"), |else_branch| else_branch.accept(self))),
+        ];
+        Self::node("If", &fields)
+    }
+
+    fn visit_print_stmt(&mut self, keyword: &Token, expressions: &[Expr]) -> String {
+        Self::node("Print", &[("keyword", Self::token(keyword)), ("expressions", self.expressions(expressions))])
+    }
+
+    fn visit_return_stmt(&mut self, keyword: &Token, value: Option<&Expr>) -> String {
+        let value = value.map_or(String::from("null"), |value| value.accept(self));
+        Self::node("Return", &[("keyword", Self::token(keyword)), ("value", value)])
+    }
+
+    fn visit_var_stmt(&mut self, name: &Token, initializer: &Expr) -> String {
+        Self::node("Var", &[("name", Self::token(name)), ("initializer", initializer.accept(self))])
+    }
+
+    fn visit_while_stmt(&mut self, keyword: &Token, condition: &Expr, body: &Stmt, increment: Option<&Expr>) -> String {
+        let fields = [
+            ("keyword", Self::token(keyword)),
+            ("condition", condition.accept(self)),
+            ("body", body.accept(self)),
+            ("increment", increment.map_or(String::from("null"), |increment| increment.accept(self))),
+        ];
+        Self::node("While", &fields)
+    }
+}
@@ -0,0 +1,170 @@
+use crate::expressions::{Expr, ExprVisitor, LiteralValue};
+use crate::lexer::Token;
+use crate::statements::{Stmt, StmtVisitor};
+
+// Prints the AST as parenthesized prefix notation, e.g. `1 + 2 * 3` becomes `(+ 1 (* 2 3))`.
+// It only looks at the tree and has no interpreter to run anything with, so the REPL can print a statement
+// before running it without a call in it happening twice
+pub struct AstPrinter;
+
+impl AstPrinter {
+    pub fn print_stmt(&mut self, statement: &Stmt) -> String {
+        statement.accept(self)
+    }
+
+    pub fn print_expr(&mut self, expression: &Expr) -> String {
+        expression.accept(self)
+    }
+
+    fn parenthesize(&mut self, name: &str, expressions: &[&Expr]) -> String {
+        let mut output = format!("({}", name);
+        for expression in expressions {
+            output.push(' ');
+            output.push_str(&expression.accept(self));
+        }
+        output.push(')');
+        output
+    }
+}
+
+impl ExprVisitor<String> for AstPrinter {
+    fn visit_array_literal_expr(&mut self, _bracket: &Token, elements: &[Expr]) -> String {
+        let elements: Vec<&Expr> = elements.iter().collect();
+        self.parenthesize("array", &elements)
+    }
+
+    fn visit_assign_expr(&mut self, name: &Token, value: &Expr) -> String {
+        self.parenthesize(&format!("= {}", name.value), &[value])
+    }
+
+    fn visit_binary_expr(&mut self, left: &Expr, operator: &Token, right: &Expr) -> String {
+        self.parenthesize(&operator.value, &[left, right])
+    }
+
+    fn visit_call_expr(&mut self, callee: &Expr, _paren: &Token, arguments: &[Expr]) -> String {
+        let mut expressions = vec![callee];
+        expressions.extend(arguments.iter());
+        self.parenthesize("call", &expressions)
+    }
+
+    fn visit_grouping_expr(&mut self, expression: &Expr) -> String {
+        self.parenthesize("group", &[expression])
+    }
+
+    fn visit_interpolation_expr(&mut self, _string: &Token, parts: &[Expr]) -> String {
+        let parts: Vec<&Expr> = parts.iter().collect();
+        self.parenthesize("interpolation", &parts)
+    }
+
+    fn visit_lambda_expr(&mut self, params: &[Token], body: &[Stmt]) -> String {
+        let params: Vec<&str> = params.iter().map(|param| param.value.as_str()).collect();
+        format!("(lambda ({}) {})", params.join(" "), self.visit_block_stmt(body))
+    }
+
+    fn visit_literal_expr(&mut self, value: &LiteralValue, token: Option<&Token>) -> String {
+        match (value, token) {
+            // Numbers are printed the way they were written, `1.50` stays `1.50` instead of becoming `1.5`
+            (LiteralValue::Number(_), Some(token)) => token.value.clone(),
+            (LiteralValue::Text(val), _) => format!("\"{}\"", val),
+            _ => value.to_string(),
+        }
+    }
+
+    fn visit_map_literal_expr(&mut self, _brace: &Token, entries: &[(Expr, Expr)]) -> String {
+        let expressions: Vec<&Expr> = entries.iter().flat_map(|(key, value)| [key, value]).collect();
+        self.parenthesize("map", &expressions)
+    }
+
+    fn visit_logical_expr(&mut self, left: &Expr, operator: &Token, right: &Expr) -> String {
+        self.parenthesize(&operator.value, &[left, right])
+    }
+
+    fn visit_index_expr(&mut self, target: &Expr, _bracket: &Token, index: &Expr) -> String {
+        self.parenthesize("index", &[target, index])
+    }
+
+    fn visit_index_assign_expr(&mut self, target: &Expr, _bracket: &Token, index: &Expr, value: &Expr) -> String {
+        let target = self.parenthesize("index", &[target, index]);
+        self.parenthesize(&format!("= {}", target), &[value])
+    }
+
+    fn visit_tuple_expr(&mut self, _paren: &Token, elements: &[Expr]) -> String {
+        let elements: Vec<&Expr> = elements.iter().collect();
+        self.parenthesize("tuple", &elements)
+    }
+
+    fn visit_ternary_expr(&mut self, condition: &Expr, _question: &Token, then_branch: &Expr, else_branch: &Expr) -> String {
+        self.parenthesize("ternary", &[condition, then_branch, else_branch])
+    }
+
+    fn visit_unary_expr(&mut self, operator: &Token, right: &Expr) -> String {
+        self.parenthesize(&operator.value, &[right])
+    }
+
+    fn visit_variable_expr(&mut self, name: &Token) -> String {
+        name.value.clone()
+    }
+}
+
+impl StmtVisitor<String> for AstPrinter {
+    fn visit_block_stmt(&mut self, statements: &[Stmt]) -> String {
+        let mut output = String::from("(block");
+        for statement in statements {
+            output.push(' ');
+            output.push_str(&statement.accept(self));
+        }
+        output.push(')');
+        output
+    }
+
+    fn visit_break_stmt(&mut self, _keyword: &Token) -> String {
+        String::from("(break)")
+    }
+
+    fn visit_continue_stmt(&mut self, _keyword: &Token) -> String {
+        String::from("(continue)")
+    }
+
+    fn visit_expression_stmt(&mut self, expression: &Expr) -> String {
+        self.parenthesize(";", &[expression])
+    }
+
+    fn visit_function_stmt(&mut self, name: &Token, params: &[Token], body: &[Stmt]) -> String {
+        let params: Vec<&str> = params.iter().map(|param| param.value.as_str()).collect();
+        format!("(fun {} ({}) {})", name.value, params.join(" "), self.visit_block_stmt(body))
+    }
+
+    fn visit_if_stmt(&mut self, _keyword: &Token, condition: &Expr, then_branch: &Stmt, else_branch: Option<&Stmt>) -> String {
+        let mut output = format!("(if {} {}", condition.accept(self), then_branch.accept(self));
+        if let Some(else_branch) = else_branch {
+            output.push_str(&format!(" else {}", else_branch.accept(self)));
+        }
+        output.push(')');
+        output
+    }
+
+    fn visit_print_stmt(&mut self, _keyword: &Token, expressions: &[Expr]) -> String {
+        let expressions: Vec<&Expr> = expressions.iter().collect();
+        self.parenthesize("print", &expressions)
+    }
+
+    fn visit_return_stmt(&mut self, _keyword: &Token, value: Option<&Expr>) -> String {
+        match value {
+            Some(value) => self.parenthesize("return", &[value]),
+            None => String::from("(return)"),
+        }
+    }
+
+    fn visit_var_stmt(&mut self, name: &Token, initializer: &Expr) -> String {
+        self.parenthesize(&format!("var {}", name.value), &[initializer])
+    }
+
+    fn visit_while_stmt(&mut self, _keyword: &Token, condition: &Expr, body: &Stmt, increment: Option<&Expr>) -> String {
+        let mut output = format!("(while {} {}", condition.accept(self), body.accept(self));
+        if let Some(increment) = increment {
+            output.push_str(&format!(" then {}", increment.accept(self)));
+        }
+        output.push(')');
+        output
+    }
+}
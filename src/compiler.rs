@@ -0,0 +1,736 @@
+use crate::errors::CompilerError;
+use crate::expressions::{Expr, ExprVisitor, LiteralValue};
+use crate::lexer::{Token, TokenType};
+use crate::statements::{Stmt, StmtVisitor};
+use crate::vm::{Chunk, CompiledFunction, Constant, OpCode};
+
+use std::rc::Rc;
+
+// Turns the AST into bytecode for the VM, for now only arithmetic and comparisons, variables, functions and
+// closures, expression statements, print, blocks, if and while can be compiled
+pub struct Compiler {
+    // The function being compiled is last, the ones it is declared in come before it. The first one is the chunk
+    // itself
+    functions: Vec<FunctionState>,
+    // Position of the last token seen, every instruction emitted is tagged with its line
+    line: usize,
+    col: usize,
+}
+
+// Every variable is a slot on the stack, found by its name when the function is compiled. A variable of an
+// enclosing function is captured as an upvalue instead
+struct FunctionState {
+    name: Option<String>,
+    arity: usize,
+    chunk: Chunk,
+    // In the order their slots are on the stack
+    locals: Vec<Local>,
+    upvalues: Vec<UpvalueSource>,
+    scope_depth: usize,
+}
+
+struct Local {
+    name: String,
+    depth: usize,
+    // A captured local is closed instead of popped when its scope ends
+    captured: bool,
+}
+
+// Where a closure gets a variable from when it is created: a slot of the function it is created in, or one of
+// that function's own upvalues
+#[derive(Clone, Copy, PartialEq)]
+struct UpvalueSource {
+    is_local: bool,
+    index: u8,
+}
+
+impl FunctionState {
+    fn new(name: Option<String>, arity: usize) -> Self {
+        FunctionState {
+            name,
+            arity,
+            chunk: Chunk::new(),
+            locals: Vec::new(),
+            upvalues: Vec::new(),
+            scope_depth: 0,
+        }
+    }
+
+    fn resolve_local(&self, name: &str) -> Option<u8> {
+        // Searched from the innermost scope out, so a variable shadows the ones with its name around it
+        let slot = self.locals.iter().rposition(|local| local.name == name)?;
+        Some(slot as u8)
+    }
+}
+
+impl Default for Compiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Compiler {
+    pub fn new() -> Self {
+        Compiler {
+            functions: vec![FunctionState::new(None, 0)],
+            line: 1,
+            col: 0,
+        }
+    }
+
+    // Move to a token, the instructions emitted from here on belong to its line
+    fn at(&mut self, token: &Token) {
+        self.line = token.line;
+        self.col = token.col;
+    }
+
+    // Compile a single expression into a chunk that leaves its value on top of the stack and returns
+    pub fn compile_expr(mut self, expression: &Expr) -> Result<Chunk, CompilerError> {
+        expression.accept(&mut self)?;
+        self.emit_op(OpCode::Return);
+        Ok(self.functions.remove(0).chunk)
+    }
+
+    // Compile a whole program, the chunk runs the statements one after the other and then returns nil
+    pub fn compile(mut self, statements: &[Stmt]) -> Result<Chunk, CompilerError> {
+        self.statements(statements)?;
+        self.emit_op(OpCode::Nil);
+        self.emit_op(OpCode::Return);
+        Ok(self.functions.remove(0).chunk)
+    }
+
+    fn current(&mut self) -> &mut FunctionState {
+        let last = self.functions.len() - 1;
+        &mut self.functions[last]
+    }
+
+    fn chunk(&mut self) -> &mut Chunk {
+        &mut self.current().chunk
+    }
+
+    fn emit_op(&mut self, op: OpCode) {
+        self.emit_byte(op as u8);
+    }
+
+    fn emit_byte(&mut self, byte: u8) {
+        let line = self.line;
+        self.chunk().write_chunk(byte, line);
+    }
+
+    // Functions are hoisted like in the tree-walker, their names are declared before the first statement so
+    // functions can call each other whatever order they are declared in. Unlike the tree-walker, each closure is
+    // only made where it is declared, calling a function before that finds nil
+    fn statements(&mut self, statements: &[Stmt]) -> Result<(), CompilerError> {
+        for statement in statements {
+            if let Stmt::Function { name, .. } = statement {
+                if self.declared_in_scope(&name.value).is_none() {
+                    self.at(name);
+                    self.emit_op(OpCode::Nil);
+                    self.add_local(name)?;
+                }
+            }
+        }
+        for statement in statements {
+            statement.accept(self)?;
+        }
+        Ok(())
+    }
+
+    fn begin_scope(&mut self) {
+        self.current().scope_depth += 1;
+    }
+
+    // The values of the scope's variables are still on the stack, they are popped, or closed if a closure
+    // captured them
+    fn end_scope(&mut self) {
+        let function = self.current();
+        function.scope_depth -= 1;
+        let depth = function.scope_depth;
+        while let Some(local) = self.current().locals.last() {
+            if local.depth <= depth {
+                break;
+            }
+            let op = if local.captured { OpCode::CloseUpvalue } else { OpCode::Pop };
+            self.current().locals.pop();
+            self.emit_op(op);
+        }
+    }
+
+    // Slot of a variable with that name declared in the innermost scope, declaring it again reuses it like the
+    // tree-walker defines the name again
+    fn declared_in_scope(&self, name: &str) -> Option<u8> {
+        let function = &self.functions[self.functions.len() - 1];
+        let slot = function.locals.iter().rposition(|local| local.name == name && local.depth == function.scope_depth)?;
+        Some(slot as u8)
+    }
+
+    // The value of the variable is whatever is on top of the stack right now
+    fn add_local(&mut self, name: &Token) -> Result<u8, CompilerError> {
+        let function = self.current();
+        let slot = match u8::try_from(function.locals.len()) {
+            Ok(slot) => slot,
+            Err(_) => {
+                return Err(CompilerError {
+                    message: "Too many variables in one function".to_string(),
+                    line: name.line,
+                    col: name.col,
+                })
+            }
+        };
+        let depth = function.scope_depth;
+        function.locals.push(Local { name: name.value.clone(), depth, captured: false });
+        Ok(slot)
+    }
+
+    // Upvalue index of a variable declared in a function around the one at `function`, None if there is none.
+    // The functions in between capture it too, so each closure can hand it to the next one when it is created
+    fn resolve_upvalue(&mut self, function: usize, name: &Token) -> Result<Option<u8>, CompilerError> {
+        if function == 0 {
+            return Ok(None);
+        }
+
+        if let Some(slot) = self.functions[function - 1].resolve_local(&name.value) {
+            self.functions[function - 1].locals[slot as usize].captured = true;
+            return self.add_upvalue(function, UpvalueSource { is_local: true, index: slot }, name).map(Some);
+        }
+        match self.resolve_upvalue(function - 1, name)? {
+            Some(index) => self.add_upvalue(function, UpvalueSource { is_local: false, index }, name).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn add_upvalue(&mut self, function: usize, source: UpvalueSource, name: &Token) -> Result<u8, CompilerError> {
+        let upvalues = &mut self.functions[function].upvalues;
+        if let Some(index) = upvalues.iter().position(|upvalue| *upvalue == source) {
+            return Ok(index as u8);
+        }
+        match u8::try_from(upvalues.len()) {
+            Ok(index) => {
+                upvalues.push(source);
+                Ok(index)
+            }
+            Err(_) => Err(CompilerError {
+                message: "Too many captured variables in one function".to_string(),
+                line: name.line,
+                col: name.col,
+            }),
+        }
+    }
+
+    // Which instruction reads or assigns the variable and its operand
+    fn resolve(&mut self, name: &Token) -> Result<(OpCode, OpCode, u8), CompilerError> {
+        if let Some(slot) = self.current().resolve_local(&name.value) {
+            return Ok((OpCode::GetLocal, OpCode::SetLocal, slot));
+        }
+        match self.resolve_upvalue(self.functions.len() - 1, name)? {
+            Some(index) => Ok((OpCode::GetUpvalue, OpCode::SetUpvalue, index)),
+            None => Err(CompilerError {
+                message: format!("Undefined variable '{}'", name.value),
+                line: name.line,
+                col: name.col,
+            }),
+        }
+    }
+
+    // The body is compiled as a function of its own, then a CLOSURE in the enclosing function creates it with the
+    // variables it captured. Slot 0 of the new function is the closure being called, the parameters come after
+    fn function(&mut self, name: Option<&Token>, params: &[Token], body: &[Stmt]) -> Result<(), CompilerError> {
+        let mut function = FunctionState::new(name.map(|name| name.value.clone()), params.len());
+        function.scope_depth = 1;
+        function.locals.push(Local { name: String::new(), depth: 1, captured: false });
+        self.functions.push(function);
+        for param in params {
+            self.add_local(param)?;
+        }
+        self.statements(body)?;
+        self.emit_op(OpCode::Nil);
+        self.emit_op(OpCode::Return);
+
+        let function = self.functions.remove(self.functions.len() - 1);
+        let compiled = CompiledFunction {
+            name: function.name,
+            arity: function.arity,
+            upvalue_count: function.upvalues.len(),
+            chunk: function.chunk,
+        };
+        let index = self.chunk().add_constant(Constant::Function(Rc::new(compiled)));
+        let index = match u8::try_from(index) {
+            Ok(index) => index,
+            Err(_) => return Err(self.unsupported_here("A function past the first 256 constants")),
+        };
+        self.emit_op(OpCode::Closure);
+        self.emit_byte(index);
+        for upvalue in function.upvalues {
+            self.emit_byte(upvalue.is_local as u8);
+            self.emit_byte(upvalue.index);
+        }
+        Ok(())
+    }
+
+    // The first 256 constants fit in a one byte operand, the ones after need CONSTANT_LONG and three bytes
+    fn emit_constant(&mut self, constant: Constant) -> Result<(), CompilerError> {
+        let index = self.chunk().add_constant(constant);
+        if let Ok(index) = u8::try_from(index) {
+            self.emit_op(OpCode::Constant);
+            self.emit_byte(index);
+            return Ok(());
+        }
+
+        match u32::try_from(index) {
+            Ok(index) if index < 1 << 24 => {
+                self.emit_op(OpCode::ConstantLong);
+                for byte in &index.to_be_bytes()[1..] {
+                    self.emit_byte(*byte);
+                }
+                Ok(())
+            }
+            _ => Err(CompilerError {
+                message: "Too many constants in one chunk".to_string(),
+                line: self.line,
+                col: self.col,
+            }),
+        }
+    }
+
+    // Emit a jump with a placeholder distance and return where the distance goes, patch_jump fills it in later
+    fn emit_jump(&mut self, op: OpCode) -> usize {
+        self.emit_op(op);
+        self.emit_byte(0xff);
+        self.emit_byte(0xff);
+        self.chunk().code_len() - 2
+    }
+
+    // Make the jump whose distance is at `operand` land on the next instruction to be emitted
+    fn patch_jump(&mut self, operand: usize, keyword: &Token) -> Result<(), CompilerError> {
+        let distance = self.chunk().code_len() - operand - 2;
+        let [high, low] = self.jump_distance(distance, keyword)?;
+        self.chunk().patch(operand, high);
+        self.chunk().patch(operand + 1, low);
+        Ok(())
+    }
+
+    fn emit_loop(&mut self, loop_start: usize, keyword: &Token) -> Result<(), CompilerError> {
+        self.emit_op(OpCode::Loop);
+        let distance = self.chunk().code_len() + 2 - loop_start;
+        let [high, low] = self.jump_distance(distance, keyword)?;
+        self.emit_byte(high);
+        self.emit_byte(low);
+        Ok(())
+    }
+
+    fn jump_distance(&self, distance: usize, keyword: &Token) -> Result<[u8; 2], CompilerError> {
+        match u16::try_from(distance) {
+            Ok(distance) => Ok(distance.to_be_bytes()),
+            Err(_) => Err(CompilerError {
+                message: "Too much code to jump over".to_string(),
+                line: keyword.line,
+                col: keyword.col,
+            }),
+        }
+    }
+
+    // For what has no token of its own to point at, the error is at the last token seen
+    fn unsupported_here(&self, what: &str) -> CompilerError {
+        CompilerError {
+            message: format!("{} can't be compiled to bytecode yet", what),
+            line: self.line,
+            col: self.col,
+        }
+    }
+
+    fn unsupported(&self, what: &str, token: &Token) -> CompilerError {
+        CompilerError {
+            message: format!("{} can't be compiled to bytecode yet", what),
+            line: token.line,
+            col: token.col,
+        }
+    }
+}
+
+impl ExprVisitor<Result<(), CompilerError>> for Compiler {
+    fn visit_array_literal_expr(&mut self, bracket: &Token, _elements: &[Expr]) -> Result<(), CompilerError> {
+        Err(self.unsupported("Arrays", bracket))
+    }
+
+    fn visit_assign_expr(&mut self, name: &Token, value: &Expr) -> Result<(), CompilerError> {
+        value.accept(self)?;
+        self.at(name);
+        let (_, set, operand) = self.resolve(name)?;
+        self.emit_op(set);
+        self.emit_byte(operand);
+        Ok(())
+    }
+
+    fn visit_binary_expr(&mut self, left: &Expr, operator: &Token, right: &Expr) -> Result<(), CompilerError> {
+        left.accept(self)?;
+        right.accept(self)?;
+
+        self.at(operator);
+        // There are no opcodes for !=, >= and <=, they are the opposite of ==, < and >. That isn't quite right
+        // for NaN, where all of them are false
+        let ops: &[OpCode] = match operator.token_type {
+            TokenType::Plus => &[OpCode::Addition],
+            TokenType::Minus => &[OpCode::Subtraction],
+            TokenType::Star => &[OpCode::Multiplication],
+            TokenType::Slash => &[OpCode::Division],
+            TokenType::Percent => &[OpCode::Modulo],
+            TokenType::EqualEqual => &[OpCode::Equal],
+            TokenType::BangEqual => &[OpCode::Equal, OpCode::Not],
+            TokenType::Greater => &[OpCode::Greater],
+            TokenType::GreaterEqual => &[OpCode::Less, OpCode::Not],
+            TokenType::Less => &[OpCode::Less],
+            TokenType::LessEqual => &[OpCode::Greater, OpCode::Not],
+            _ => return Err(self.unsupported(&format!("Operator '{}'", operator.value), operator)),
+        };
+        for op in ops {
+            self.emit_op(*op);
+        }
+        Ok(())
+    }
+
+    // The parser already refuses more than 255 arguments, so the count fits in CALL's operand
+    fn visit_call_expr(&mut self, callee: &Expr, paren: &Token, arguments: &[Expr]) -> Result<(), CompilerError> {
+        callee.accept(self)?;
+        for argument in arguments {
+            argument.accept(self)?;
+        }
+        self.at(paren);
+        let count = match u8::try_from(arguments.len()) {
+            Ok(count) => count,
+            Err(_) => return Err(self.unsupported("A call with more than 255 arguments", paren)),
+        };
+        self.emit_op(OpCode::Call);
+        self.emit_byte(count);
+        Ok(())
+    }
+
+    fn visit_grouping_expr(&mut self, expression: &Expr) -> Result<(), CompilerError> {
+        expression.accept(self)
+    }
+
+    fn visit_interpolation_expr(&mut self, string: &Token, _parts: &[Expr]) -> Result<(), CompilerError> {
+        Err(self.unsupported("String interpolation", string))
+    }
+
+    fn visit_lambda_expr(&mut self, params: &[Token], body: &[Stmt]) -> Result<(), CompilerError> {
+        self.function(None, params, body)
+    }
+
+    // A literal the parser made up has no token, it stays on the line of the token before it
+    fn visit_literal_expr(&mut self, value: &LiteralValue, token: Option<&Token>) -> Result<(), CompilerError> {
+        if let Some(token) = token {
+            self.at(token);
+        }
+        match value {
+            // The tree-walker treats every number as a float, so the bytecode does the same
+            LiteralValue::Number(number) => {
+                self.emit_constant(Constant::Float(*number))
+            }
+            LiteralValue::Bool(true) => {
+                self.emit_op(OpCode::True);
+                Ok(())
+            }
+            LiteralValue::Bool(false) => {
+                self.emit_op(OpCode::False);
+                Ok(())
+            }
+            LiteralValue::Nil => {
+                self.emit_op(OpCode::Nil);
+                Ok(())
+            }
+            _ => Err(self.unsupported_here("This literal")),
+        }
+    }
+
+    fn visit_map_literal_expr(&mut self, brace: &Token, _entries: &[(Expr, Expr)]) -> Result<(), CompilerError> {
+        Err(self.unsupported("Map", brace))
+    }
+
+    fn visit_logical_expr(&mut self, _left: &Expr, operator: &Token, _right: &Expr) -> Result<(), CompilerError> {
+        Err(self.unsupported("Logical expression", operator))
+    }
+
+    fn visit_index_expr(&mut self, _target: &Expr, bracket: &Token, _index: &Expr) -> Result<(), CompilerError> {
+        Err(self.unsupported("Indexing", bracket))
+    }
+
+    fn visit_index_assign_expr(&mut self, _target: &Expr, bracket: &Token, _index: &Expr, _value: &Expr) -> Result<(), CompilerError> {
+        Err(self.unsupported("Index assignment", bracket))
+    }
+
+    fn visit_tuple_expr(&mut self, paren: &Token, _elements: &[Expr]) -> Result<(), CompilerError> {
+        Err(self.unsupported("Tuples", paren))
+    }
+
+    fn visit_ternary_expr(&mut self, _condition: &Expr, question: &Token, _then_branch: &Expr, _else_branch: &Expr) -> Result<(), CompilerError> {
+        Err(self.unsupported("Ternary expressions", question))
+    }
+
+    fn visit_unary_expr(&mut self, operator: &Token, right: &Expr) -> Result<(), CompilerError> {
+        right.accept(self)?;
+
+        self.at(operator);
+        match operator.token_type {
+            TokenType::Minus => {
+                self.emit_op(OpCode::Negate);
+                Ok(())
+            }
+            TokenType::Bang => {
+                self.emit_op(OpCode::Not);
+                Ok(())
+            }
+            _ => Err(self.unsupported(&format!("Operator '{}'", operator.value), operator)),
+        }
+    }
+
+    fn visit_variable_expr(&mut self, name: &Token) -> Result<(), CompilerError> {
+        self.at(name);
+        let (get, _, operand) = self.resolve(name)?;
+        self.emit_op(get);
+        self.emit_byte(operand);
+        Ok(())
+    }
+}
+
+impl StmtVisitor<Result<(), CompilerError>> for Compiler {
+    fn visit_block_stmt(&mut self, statements: &[Stmt]) -> Result<(), CompilerError> {
+        self.begin_scope();
+        self.statements(statements)?;
+        self.end_scope();
+        Ok(())
+    }
+
+    fn visit_break_stmt(&mut self, keyword: &Token) -> Result<(), CompilerError> {
+        Err(self.unsupported("Break", keyword))
+    }
+
+    fn visit_continue_stmt(&mut self, keyword: &Token) -> Result<(), CompilerError> {
+        Err(self.unsupported("Continue", keyword))
+    }
+
+    fn visit_expression_stmt(&mut self, expression: &Expr) -> Result<(), CompilerError> {
+        expression.accept(self)?;
+        self.emit_op(OpCode::Pop);
+        Ok(())
+    }
+
+    // The name was declared when the statements around it were hoisted, the closure goes in its slot
+    fn visit_function_stmt(&mut self, name: &Token, params: &[Token], body: &[Stmt]) -> Result<(), CompilerError> {
+        self.at(name);
+        self.function(Some(name), params, body)?;
+        let (_, set, operand) = self.resolve(name)?;
+        self.emit_op(set);
+        self.emit_byte(operand);
+        self.emit_op(OpCode::Pop);
+        Ok(())
+    }
+
+    // JUMP_IF_FALSE leaves the condition on the stack, so each branch starts by popping it
+    fn visit_if_stmt(&mut self, keyword: &Token, condition: &Expr, then_branch: &Stmt, else_branch: Option<&Stmt>) -> Result<(), CompilerError> {
+        self.at(keyword);
+        condition.accept(self)?;
+        let then_jump = self.emit_jump(OpCode::JumpIfFalse);
+        self.emit_op(OpCode::Pop);
+        then_branch.accept(self)?;
+
+        let else_jump = self.emit_jump(OpCode::Jump);
+        self.patch_jump(then_jump, keyword)?;
+        self.emit_op(OpCode::Pop);
+        if let Some(else_branch) = else_branch {
+            else_branch.accept(self)?;
+        }
+        self.patch_jump(else_jump, keyword)
+    }
+
+    // Every value is pushed first, then a single PRINT takes them all off the stack
+    fn visit_print_stmt(&mut self, keyword: &Token, expressions: &[Expr]) -> Result<(), CompilerError> {
+        for expression in expressions {
+            expression.accept(self)?;
+        }
+        self.at(keyword);
+        let count = match u8::try_from(expressions.len()) {
+            Ok(count) => count,
+            Err(_) => return Err(self.unsupported("Printing more than 255 values", keyword)),
+        };
+        self.emit_op(OpCode::Print);
+        self.emit_byte(count);
+        Ok(())
+    }
+
+    fn visit_return_stmt(&mut self, keyword: &Token, value: Option<&Expr>) -> Result<(), CompilerError> {
+        if self.functions.len() == 1 {
+            return Err(CompilerError {
+                message: "Can't return from outside a function".to_string(),
+                line: keyword.line,
+                col: keyword.col,
+            });
+        }
+
+        self.at(keyword);
+        match value {
+            Some(value) => value.accept(self)?,
+            None => self.emit_op(OpCode::Nil),
+        }
+        self.emit_op(OpCode::Return);
+        Ok(())
+    }
+
+    // The initializer is compiled before the variable is declared, so in `var a = a;` it is the `a` from around
+    fn visit_var_stmt(&mut self, name: &Token, initializer: &Expr) -> Result<(), CompilerError> {
+        self.at(name);
+        initializer.accept(self)?;
+        self.at(name);
+        match self.declared_in_scope(&name.value) {
+            Some(slot) => {
+                self.emit_op(OpCode::SetLocal);
+                self.emit_byte(slot);
+                self.emit_op(OpCode::Pop);
+            }
+            None => {
+                self.add_local(name)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn visit_while_stmt(&mut self, keyword: &Token, condition: &Expr, body: &Stmt, increment: Option<&Expr>) -> Result<(), CompilerError> {
+        self.at(keyword);
+        let loop_start = self.chunk().code_len();
+        condition.accept(self)?;
+        let exit_jump = self.emit_jump(OpCode::JumpIfFalse);
+        self.emit_op(OpCode::Pop);
+        body.accept(self)?;
+        if let Some(increment) = increment {
+            increment.accept(self)?;
+            self.emit_op(OpCode::Pop);
+        }
+        self.emit_loop(loop_start, keyword)?;
+
+        self.patch_jump(exit_jump, keyword)?;
+        self.emit_op(OpCode::Pop);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input_stream::InputStream;
+    use crate::lexer::TokenStream;
+    use crate::parser::Parser;
+    use crate::vm::VM;
+
+    use std::cell::RefCell;
+    use std::io::{self, Write};
+
+    // Keeps what the VM prints, the test still holds a clone after the VM took its own
+    #[derive(Clone, Default)]
+    struct SharedOutput(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedOutput {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn lex(source: &str) -> Vec<Token> {
+        let mut input_stream = InputStream::new(source);
+        TokenStream::new(&mut input_stream).collect::<Result<_, _>>().unwrap()
+    }
+
+    fn compile(source: &str) -> Result<Chunk, CompilerError> {
+        let tokens = lex(source);
+        let statements = Parser::new(&tokens).parse_program().unwrap();
+        Compiler::new().compile(&statements)
+    }
+
+    fn run(source: &str) -> String {
+        let chunk = compile(source).unwrap();
+
+        let output = SharedOutput::default();
+        let mut vm = VM::from_chunk(&chunk, false, false);
+        vm.set_output(Box::new(output.clone()));
+        vm.interpret().unwrap();
+        let printed = output.0.borrow().clone();
+        String::from_utf8(printed).unwrap()
+    }
+
+    #[test]
+    fn arithmetic_expression_leaves_its_value_on_the_stack() {
+        let tokens = lex("1 + 2 * 3");
+        let expression = Parser::new(&tokens).parse_expression().unwrap();
+        let chunk = Compiler::new().compile_expr(&expression).unwrap();
+        let mut vm = VM::from_chunk(&chunk, false, false);
+        assert_eq!(vm.interpret().unwrap(), Some(Constant::Float(7.0)));
+
+        assert_eq!(run("print 1 + 2 * 3;"), "7\n");
+    }
+
+    #[test]
+    fn literals_and_print_are_on_their_own_lines() {
+        let listing = compile("print\n  1,\n  true;").unwrap().disassemble_to_string("test");
+        assert!(listing.contains("CONST FLOAT 1\t\t@2"), "{}", listing);
+        assert!(listing.contains("TRUE\t\t@3"), "{}", listing);
+        assert!(listing.contains("PRINT 2\t\t@1"), "{}", listing);
+    }
+
+    #[test]
+    fn unsupported_literal_is_reported_where_it_is() {
+        let error = compile("\n\nprint \"a\";").err().unwrap();
+        assert_eq!((error.line, error.col), (3, 7));
+    }
+
+    #[test]
+    fn closure_captures_and_mutates_an_outer_variable() {
+        let source = "
+            fun counter() {
+                var count = 0;
+                fun increment() {
+                    count = count + 1;
+                    return count;
+                }
+                return increment;
+            }
+            var first = counter();
+            first();
+            var second = counter();
+            print first(), first(), second();
+        ";
+        assert_eq!(run(source), "2 3 1\n");
+    }
+
+    #[test]
+    fn closures_of_one_scope_share_the_variable_after_it_ends() {
+        let source = "
+            var add = nil;
+            var read = nil;
+            {
+                var total = 1;
+                add = λ(n) { total = total + n; };
+                read = λ() { return total; };
+                total = 10;
+            }
+            add(5);
+            print read();
+        ";
+        assert_eq!(run(source), "15\n");
+    }
+
+    #[test]
+    fn constants_past_the_first_256_are_loaded_with_constant_long() {
+        let source: String = (0..300).map(|number| format!("print {};\n", 1000 + number)).collect();
+        let chunk = compile(&source).unwrap();
+        assert_eq!(chunk.constants().len(), 300);
+        let listing = chunk.disassemble_to_string("test");
+        assert!(listing.contains("CONST_LONG FLOAT 1299"), "{}", listing);
+
+        let printed = run(&source);
+        assert_eq!(printed.lines().count(), 300);
+        assert_eq!(printed.lines().nth(256), Some("1256"));
+        assert_eq!(printed.lines().last(), Some("1299"));
+    }
+}
@@ -0,0 +1,88 @@
+use crate::errors::{ErrorCode, InterpreterRuntimeError};
+use crate::expressions::LiteralValue;
+use crate::lexer::Token;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+// A scope of variables, every block creates a new one that points back to the scope it was created in
+pub struct Environment {
+    values: HashMap<String, LiteralValue>,
+    enclosing: Option<Rc<RefCell<Environment>>>,
+}
+
+impl Default for Environment {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Environment {
+    pub fn new() -> Self {
+        Environment {
+            values: HashMap::new(),
+            enclosing: None,
+        }
+    }
+
+    pub fn from_enclosing(enclosing: Rc<RefCell<Environment>>) -> Self {
+        Environment {
+            values: HashMap::new(),
+            enclosing: Some(enclosing),
+        }
+    }
+
+    // Variables defined in this scope only, without the enclosing ones
+    pub fn values(&self) -> &HashMap<String, LiteralValue> {
+        &self.values
+    }
+
+    // Every name that can be read from this scope, including the enclosing ones
+    pub fn visible_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.values.keys().cloned().collect();
+        if let Some(enclosing) = &self.enclosing {
+            names.extend(enclosing.borrow().visible_names());
+        }
+        names
+    }
+
+    // Gives back the value the name had in this scope if it was already defined here
+    pub fn define(&mut self, name: &str, value: LiteralValue) -> Option<LiteralValue> {
+        self.values.insert(name.to_string(), value)
+    }
+
+    pub fn get(&self, name: &Token) -> Result<LiteralValue, InterpreterRuntimeError> {
+        if let Some(value) = self.values.get(&name.value) {
+            return Ok(value.clone());
+        }
+
+        // Not in this scope, so try the enclosing ones until we run out of them
+        match &self.enclosing {
+            Some(enclosing) => enclosing.borrow().get(name),
+            None => Err(InterpreterRuntimeError {
+                code: ErrorCode::UndefinedVariable,
+                message: format!("Undefined variable '{}'", name.value),
+                line: name.line,
+                col: name.col,
+            }),
+        }
+    }
+
+    // Gives back the value the variable had before
+    pub fn assign(&mut self, name: &Token, value: LiteralValue) -> Result<LiteralValue, InterpreterRuntimeError> {
+        if let Some(old_value) = self.values.get_mut(&name.value) {
+            return Ok(std::mem::replace(old_value, value));
+        }
+
+        match &self.enclosing {
+            Some(enclosing) => enclosing.borrow_mut().assign(name, value),
+            None => Err(InterpreterRuntimeError {
+                code: ErrorCode::UndefinedVariable,
+                message: format!("Can't assign to undefined variable '{}'", name.value),
+                line: name.line,
+                col: name.col,
+            }),
+        }
+    }
+}
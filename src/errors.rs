@@ -1,6 +1,267 @@
+use std::error::Error;
+use std::fmt;
+
+// Every kind of error has a stable code, so tools can tell errors apart without matching on their message.
+// The numbers are shown as E0001 and so on, new codes are added at the end and existing ones never change
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    // Lexing
+    InvalidCharacter = 1,
+    UnterminatedString = 2,
+    InvalidOperator = 3,
+    TokenTooLong = 4,
+    // Parsing
+    UnexpectedToken = 5,
+    ExpectedExpression = 6,
+    InvalidAssignmentTarget = 7,
+    TooManyArguments = 8,
+    OutsideLoop = 9,
+    InvalidLiteral = 10,
+    NestingTooDeep = 11,
+    // Running
+    UndefinedVariable = 12,
+    TypeMismatch = 13,
+    WrongArgumentCount = 14,
+    NotCallable = 15,
+    IndexOutOfBounds = 16,
+    InvalidArgument = 17,
+    ImmutableValue = 18,
+    OutputFailed = 19,
+    ReturnOutsideFunction = 20,
+    StackOverflow = 21,
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "E{:04}", *self as u32)
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct InvalidTokenError {
+    pub code: ErrorCode,
     pub message: String,
     pub line_as_string: String,
     pub line: usize,
     pub col: usize
-}
\ No newline at end of file
+}
+
+// Same layout as a parser error, the message already says where the error is
+impl fmt::Display for InvalidTokenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{}] {}", self.code, self.message)?;
+        write!(f, "\n  | {}\n  | {:>width$}", self.line_as_string, "^", width = self.col)
+    }
+}
+
+impl Error for InvalidTokenError {}
+
+#[derive(Debug, Clone)]
+pub struct ParserError {
+    pub code: ErrorCode,
+    pub message: String,
+    // Empty when the parser wasn't given the source, the error is then shown without it
+    pub line_as_string: String,
+    pub line: usize,
+    pub col: usize
+}
+
+// The line the error is on is shown under the message, with a caret under the column
+impl fmt::Display for ParserError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{}] {}:{} {}", self.code, self.line, self.col, self.message)?;
+        if !self.line_as_string.is_empty() {
+            write!(f, "\n  | {}\n  | {:>width$}", self.line_as_string, "^", width = self.col)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for ParserError {}
+
+#[derive(Debug, Clone)]
+pub struct CompilerError {
+    pub message: String,
+    pub line: usize,
+    pub col: usize
+}
+
+impl fmt::Display for CompilerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{} {}", self.line, self.col, self.message)
+    }
+}
+
+impl Error for CompilerError {}
+
+#[derive(Debug, Clone)]
+pub struct InterpreterRuntimeError {
+    pub code: ErrorCode,
+    pub message: String,
+    pub line: usize,
+    pub col: usize
+}
+
+impl fmt::Display for InterpreterRuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{}] {}:{} {}", self.code, self.line, self.col, self.message)
+    }
+}
+
+impl Error for InterpreterRuntimeError {}
+
+// Error raised by the VM while running a chunk, bytecode only keeps track of source lines
+#[derive(Debug, Clone)]
+pub struct VMRuntimeError {
+    pub message: String,
+    pub line: usize,
+    // Offset in the chunk of the instruction that failed
+    pub offset: usize
+}
+
+impl fmt::Display for VMRuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for VMRuntimeError {}
+
+// Error raised when bytes read back from disk aren't a chunk written by Chunk::to_bytes
+#[derive(Debug, Clone)]
+pub struct ChunkDecodeError {
+    pub message: String
+}
+
+impl fmt::Display for ChunkDecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid chunk: {}", self.message)
+    }
+}
+
+impl Error for ChunkDecodeError {}
+
+// Any error from any stage, for code that runs several of them and wants one error type. The stage's own error
+// is the source, so it can still be looked at with Error::source
+#[derive(Debug, Clone)]
+pub enum WolffError {
+    Lexer(InvalidTokenError),
+    Parser(ParserError),
+    Compiler(CompilerError),
+    Runtime(InterpreterRuntimeError),
+    VMRuntime(VMRuntimeError),
+    ChunkDecode(ChunkDecodeError),
+}
+
+impl fmt::Display for WolffError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WolffError::Lexer(_) => write!(f, "Lexing failed"),
+            WolffError::Parser(_) => write!(f, "Parsing failed"),
+            WolffError::Compiler(_) => write!(f, "Compiling failed"),
+            WolffError::Runtime(_) | WolffError::VMRuntime(_) => write!(f, "Running failed"),
+            WolffError::ChunkDecode(_) => write!(f, "Loading the chunk failed"),
+        }
+    }
+}
+
+impl Error for WolffError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            WolffError::Lexer(error) => Some(error),
+            WolffError::Parser(error) => Some(error),
+            WolffError::Compiler(error) => Some(error),
+            WolffError::Runtime(error) => Some(error),
+            WolffError::VMRuntime(error) => Some(error),
+            WolffError::ChunkDecode(error) => Some(error),
+        }
+    }
+}
+
+impl From<InvalidTokenError> for WolffError {
+    fn from(error: InvalidTokenError) -> Self {
+        WolffError::Lexer(error)
+    }
+}
+
+impl From<ParserError> for WolffError {
+    fn from(error: ParserError) -> Self {
+        WolffError::Parser(error)
+    }
+}
+
+impl From<CompilerError> for WolffError {
+    fn from(error: CompilerError) -> Self {
+        WolffError::Compiler(error)
+    }
+}
+
+impl From<InterpreterRuntimeError> for WolffError {
+    fn from(error: InterpreterRuntimeError) -> Self {
+        WolffError::Runtime(error)
+    }
+}
+
+impl From<VMRuntimeError> for WolffError {
+    fn from(error: VMRuntimeError) -> Self {
+        WolffError::VMRuntime(error)
+    }
+}
+
+impl From<ChunkDecodeError> for WolffError {
+    fn from(error: ChunkDecodeError) -> Self {
+        WolffError::ChunkDecode(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The `?` is the point, each error type has to convert into Box<dyn Error> on its own
+    fn boxed<E: Error + 'static>(error: E) -> Result<(), Box<dyn Error>> {
+        Err(error)?
+    }
+
+    #[test]
+    fn every_error_type_can_be_boxed() {
+        let errors = [
+            boxed(InvalidTokenError {
+                code: ErrorCode::InvalidCharacter,
+                message: "Invalid character '@'".to_string(),
+                line_as_string: "1 @ 2".to_string(),
+                line: 1,
+                col: 3,
+            }),
+            boxed(ParserError {
+                code: ErrorCode::ExpectedExpression,
+                message: "Expected expression".to_string(),
+                line_as_string: String::new(),
+                line: 1,
+                col: 3,
+            }),
+            boxed(CompilerError { message: "Too many constants in one chunk".to_string(), line: 1, col: 3 }),
+            boxed(InterpreterRuntimeError {
+                code: ErrorCode::TypeMismatch,
+                message: "Operands of '+' must be numbers".to_string(),
+                line: 1,
+                col: 3,
+            }),
+            boxed(VMRuntimeError { message: "Stack underflow".to_string(), line: 1, offset: 0 }),
+            boxed(ChunkDecodeError { message: "not a Wolff chunk".to_string() }),
+        ];
+
+        let messages: Vec<String> = errors.into_iter().map(|error| error.unwrap_err().to_string()).collect();
+        assert_eq!(messages[0], "[E0001] Invalid character '@'\n  | 1 @ 2\n  |   ^");
+        assert_eq!(messages[1], "[E0006] 1:3 Expected expression");
+        assert_eq!(messages[2], "1:3 Too many constants in one chunk");
+        assert_eq!(messages[3], "[E0013] 1:3 Operands of '+' must be numbers");
+        assert_eq!(messages[4], "line 1: Stack underflow");
+        assert_eq!(messages[5], "Invalid chunk: not a Wolff chunk");
+
+        // WolffError keeps the error it was made from as its source
+        let error = boxed(WolffError::from(ChunkDecodeError { message: "not a Wolff chunk".to_string() })).unwrap_err();
+        assert_eq!(error.to_string(), "Loading the chunk failed");
+        assert_eq!(error.source().unwrap().to_string(), "Invalid chunk: not a Wolff chunk");
+    }
+}
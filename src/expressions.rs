@@ -0,0 +1,334 @@
+use crate::functions::{Function, NativeFunction};
+use crate::lexer::Token;
+use crate::statements::Stmt;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+// Value that an expression can evaluate to, also used to hold the literals found in the source code
+#[derive(Clone, Debug)]
+pub enum LiteralValue {
+    Number(f64),
+    Text(String),
+    // Raw bytes from a b"..." literal, indexing one gives the byte as a number
+    Bytes(Vec<u8>),
+    Bool(bool),
+    Function(Rc<Function>),
+    NativeFunction(Rc<NativeFunction>),
+    Tuple(Vec<LiteralValue>),
+    // Arrays are shared, not copied, so changing one through a variable is seen through all the others
+    Array(Rc<RefCell<Vec<LiteralValue>>>),
+    // Shared like arrays, only strings can be keys
+    Map(Rc<RefCell<HashMap<String, LiteralValue>>>),
+    Nil,
+}
+
+// Values of different types are never equal, comparing them is false and not an error
+impl PartialEq for LiteralValue {
+    fn eq(&self, other: &Self) -> bool {
+        self.equals(other, &mut Vec::new())
+    }
+}
+
+// Pairs of arrays or maps, by address, that are being compared
+type Comparing = Vec<(*const (), *const ())>;
+
+impl LiteralValue {
+    fn equals(&self, other: &Self, comparing: &mut Comparing) -> bool {
+        match (self, other) {
+            // Exact, the approx_eq native is there for numbers that went through rounding
+            (LiteralValue::Number(lhs), LiteralValue::Number(rhs)) => lhs == rhs,
+            (LiteralValue::Text(lhs), LiteralValue::Text(rhs)) => lhs == rhs,
+            (LiteralValue::Bytes(lhs), LiteralValue::Bytes(rhs)) => lhs == rhs,
+            (LiteralValue::Bool(lhs), LiteralValue::Bool(rhs)) => lhs == rhs,
+            // Functions are only equal to themselves, their bodies are never compared
+            (LiteralValue::Function(lhs), LiteralValue::Function(rhs)) => Rc::ptr_eq(lhs, rhs),
+            (LiteralValue::NativeFunction(lhs), LiteralValue::NativeFunction(rhs)) => Rc::ptr_eq(lhs, rhs),
+            // Element by element, so tuples of different lengths are never equal
+            (LiteralValue::Tuple(lhs), LiteralValue::Tuple(rhs)) => all_equal(lhs, rhs, comparing),
+            (LiteralValue::Array(lhs), LiteralValue::Array(rhs)) => {
+                compare_once(Rc::as_ptr(lhs).cast(), Rc::as_ptr(rhs).cast(), comparing, |comparing| {
+                    all_equal(&lhs.borrow(), &rhs.borrow(), comparing)
+                })
+            }
+            (LiteralValue::Map(lhs), LiteralValue::Map(rhs)) => {
+                compare_once(Rc::as_ptr(lhs).cast(), Rc::as_ptr(rhs).cast(), comparing, |comparing| {
+                    let (lhs, rhs) = (lhs.borrow(), rhs.borrow());
+                    lhs.len() == rhs.len()
+                        && lhs.iter().all(|(key, value)| rhs.get(key).is_some_and(|other| value.equals(other, comparing)))
+                })
+            }
+            (LiteralValue::Nil, LiteralValue::Nil) => true,
+            _ => false,
+        }
+    }
+}
+
+fn all_equal(lhs: &[LiteralValue], rhs: &[LiteralValue], comparing: &mut Comparing) -> bool {
+    lhs.len() == rhs.len() && lhs.iter().zip(rhs).all(|(lhs, rhs)| lhs.equals(rhs, comparing))
+}
+
+// An array or map can contain itself, so comparing two of them can lead back to the same two. That comparison is
+// already being done further up, so it counts as equal here instead of recursing forever
+fn compare_once(
+    lhs: *const (),
+    rhs: *const (),
+    comparing: &mut Comparing,
+    compare: impl FnOnce(&mut Comparing) -> bool,
+) -> bool {
+    if lhs == rhs || comparing.contains(&(lhs, rhs)) {
+        return true;
+    }
+
+    comparing.push((lhs, rhs));
+    let equal = compare(comparing);
+    comparing.pop();
+    equal
+}
+
+impl fmt::Display for LiteralValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LiteralValue::Number(val) => write!(f, "{}", format_number(*val)),
+            LiteralValue::Text(val) => write!(f, "{}", val),
+            // Written the way it would be in the source, so the bytes that aren't printable can still be seen
+            LiteralValue::Bytes(bytes) => {
+                write!(f, "b\"")?;
+                for byte in bytes {
+                    match byte {
+                        b'"' | b'\\' => write!(f, "\\{}", *byte as char)?,
+                        b' '..=b'~' => write!(f, "{}", *byte as char)?,
+                        _ => write!(f, "\\x{:02x}", byte)?,
+                    }
+                }
+                write!(f, "\"")
+            }
+            LiteralValue::Bool(val) => write!(f, "{}", val),
+            LiteralValue::Function(function) => write!(f, "{}", function),
+            LiteralValue::NativeFunction(function) => write!(f, "{}", function),
+            LiteralValue::Tuple(_) | LiteralValue::Array(_) | LiteralValue::Map(_) => {
+                write!(f, "{}", self.format(false, &mut Vec::new()))
+            }
+            LiteralValue::Nil => write!(f, "nil"),
+        }
+    }
+}
+
+impl LiteralValue {
+    // Like Display, but strings keep their quotes, also inside arrays, tuples and maps, so `"1"` can be told
+    // apart from `1` and an empty string still shows up
+    pub fn repr(&self) -> String {
+        self.format(true, &mut Vec::new())
+    }
+
+    // An array or map that holds itself is printed as `[...]` or `{...}` where it comes back round
+    fn format(&self, quoted: bool, formatting: &mut Formatting) -> String {
+        match self {
+            LiteralValue::Text(val) if quoted => format!("\"{}\"", val.replace('\\', "\\\\").replace('"', "\\\"")),
+            LiteralValue::Tuple(elements) => {
+                let elements: Vec<String> = elements.iter().map(|element| element.format(quoted, formatting)).collect();
+                format!("({})", elements.join(", "))
+            }
+            LiteralValue::Array(array) => format_once(Rc::as_ptr(array).cast(), "[...]", formatting, |formatting| {
+                let array = array.borrow();
+                let elements: Vec<String> = array.iter().map(|element| element.format(quoted, formatting)).collect();
+                format!("[{}]", elements.join(", "))
+            }),
+            // Sorted by key, the order of a HashMap changes from run to run
+            LiteralValue::Map(map) => format_once(Rc::as_ptr(map).cast(), "{...}", formatting, |formatting| {
+                let map = map.borrow();
+                let mut entries: Vec<(&String, &LiteralValue)> = map.iter().collect();
+                entries.sort_by_key(|(key, _)| *key);
+                let entries: Vec<String> = entries
+                    .iter()
+                    .map(|(key, value)| {
+                        let key = if quoted { LiteralValue::Text(key.to_string()).repr() } else { key.to_string() };
+                        format!("{}: {}", key, value.format(quoted, formatting))
+                    })
+                    .collect();
+                format!("{{{}}}", entries.join(", "))
+            }),
+            _ => self.to_string(),
+        }
+    }
+}
+
+// Arrays and maps that are being printed, from the outermost in
+type Formatting = Vec<*const ()>;
+
+fn format_once(
+    pointer: *const (),
+    cycle: &str,
+    formatting: &mut Formatting,
+    format: impl FnOnce(&mut Formatting) -> String,
+) -> String {
+    if formatting.contains(&pointer) {
+        return cycle.to_string();
+    }
+
+    formatting.push(pointer);
+    let text = format(formatting);
+    formatting.pop();
+    text
+}
+
+// Integral numbers are printed without a fractional part (`4.0` is `4`), anything else with as few digits
+// as it takes to read back the same number (`0.1 + 0.2` is `0.30000000000000004`). Zero never gets a sign,
+// and NaN is lowercase like inf
+pub fn format_number(number: f64) -> String {
+    if number == 0.0 {
+        String::from("0")
+    } else if number.is_nan() {
+        String::from("nan")
+    } else {
+        number.to_string()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Expr {
+    // The bracket is the opening one, like for MapLiteral
+    ArrayLiteral {
+        bracket: Token,
+        elements: Vec<Expr>,
+    },
+    Assign {
+        name: Token,
+        value: Box<Expr>,
+    },
+    Binary {
+        left: Box<Expr>,
+        operator: Token,
+        right: Box<Expr>,
+    },
+    // The paren is the closing parenthesis of the call, errors from the call are reported there
+    Call {
+        callee: Box<Expr>,
+        paren: Token,
+        arguments: Vec<Expr>,
+    },
+    Grouping {
+        expression: Box<Expr>,
+    },
+    // `"a ${b} c"`, the text between the expressions is in string literals. The parts are joined the way print
+    // shows them, nothing is added in between. The string is the token the whole string was read from
+    Interpolation {
+        string: Token,
+        parts: Vec<Expr>,
+    },
+    // `target[index]`, the bracket is the opening one and errors from indexing are reported there
+    Index {
+        target: Box<Expr>,
+        bracket: Token,
+        index: Box<Expr>,
+    },
+    // `target[index] = value`, kept apart from Assign because the target isn't a variable name
+    IndexAssign {
+        target: Box<Expr>,
+        bracket: Token,
+        index: Box<Expr>,
+        value: Box<Expr>,
+    },
+    // Always has at least two elements, `(a)` is a grouping. The paren is the opening one
+    Tuple {
+        paren: Token,
+        elements: Vec<Expr>,
+    },
+    // Anonymous function, written `lambda (a, b) { ... }` or `λ (a, b) { ... }`
+    Lambda {
+        params: Vec<Token>,
+        body: Vec<Stmt>,
+    },
+    // The token is the literal as it was written in the source (e.g. `1.50`), None if the parser made it up. It is
+    // boxed to keep literals, the most common node, small
+    Literal {
+        value: LiteralValue,
+        token: Option<Box<Token>>,
+    },
+    // The brace is the opening one, errors about the keys are reported there
+    MapLiteral {
+        brace: Token,
+        entries: Vec<(Expr, Expr)>,
+    },
+    Logical {
+        left: Box<Expr>,
+        operator: Token,
+        right: Box<Expr>,
+    },
+    Ternary {
+        condition: Box<Expr>,
+        question: Token,
+        then_branch: Box<Expr>,
+        else_branch: Box<Expr>,
+    },
+    Unary {
+        operator: Token,
+        right: Box<Expr>,
+    },
+    Variable {
+        name: Token,
+    },
+}
+
+// Every pass over the expression tree (printing, interpreting etc.) implements this trait
+pub trait ExprVisitor<T> {
+    fn visit_array_literal_expr(&mut self, bracket: &Token, elements: &[Expr]) -> T;
+    fn visit_assign_expr(&mut self, name: &Token, value: &Expr) -> T;
+    fn visit_binary_expr(&mut self, left: &Expr, operator: &Token, right: &Expr) -> T;
+    fn visit_call_expr(&mut self, callee: &Expr, paren: &Token, arguments: &[Expr]) -> T;
+    fn visit_grouping_expr(&mut self, expression: &Expr) -> T;
+    fn visit_interpolation_expr(&mut self, string: &Token, parts: &[Expr]) -> T;
+    fn visit_lambda_expr(&mut self, params: &[Token], body: &[Stmt]) -> T;
+    fn visit_literal_expr(&mut self, value: &LiteralValue, token: Option<&Token>) -> T;
+    fn visit_map_literal_expr(&mut self, brace: &Token, entries: &[(Expr, Expr)]) -> T;
+    fn visit_logical_expr(&mut self, left: &Expr, operator: &Token, right: &Expr) -> T;
+    fn visit_index_expr(&mut self, target: &Expr, bracket: &Token, index: &Expr) -> T;
+    fn visit_index_assign_expr(&mut self, target: &Expr, bracket: &Token, index: &Expr, value: &Expr) -> T;
+    fn visit_tuple_expr(&mut self, paren: &Token, elements: &[Expr]) -> T;
+    fn visit_ternary_expr(&mut self, condition: &Expr, question: &Token, then_branch: &Expr, else_branch: &Expr) -> T;
+    fn visit_unary_expr(&mut self, operator: &Token, right: &Expr) -> T;
+    fn visit_variable_expr(&mut self, name: &Token) -> T;
+}
+
+impl Expr {
+    pub fn accept<T>(&self, visitor: &mut dyn ExprVisitor<T>) -> T {
+        match self {
+            Expr::ArrayLiteral { bracket, elements } => visitor.visit_array_literal_expr(bracket, elements),
+            Expr::Assign { name, value } => visitor.visit_assign_expr(name, value),
+            Expr::Binary { left, operator, right } => visitor.visit_binary_expr(left, operator, right),
+            Expr::Call { callee, paren, arguments } => visitor.visit_call_expr(callee, paren, arguments),
+            Expr::Grouping { expression } => visitor.visit_grouping_expr(expression),
+            Expr::Interpolation { string, parts } => visitor.visit_interpolation_expr(string, parts),
+            Expr::Lambda { params, body } => visitor.visit_lambda_expr(params, body),
+            Expr::Literal { value, token } => visitor.visit_literal_expr(value, token.as_deref()),
+            Expr::MapLiteral { brace, entries } => visitor.visit_map_literal_expr(brace, entries),
+            Expr::Logical { left, operator, right } => visitor.visit_logical_expr(left, operator, right),
+            Expr::Index { target, bracket, index } => visitor.visit_index_expr(target, bracket, index),
+            Expr::IndexAssign { target, bracket, index, value } => {
+                visitor.visit_index_assign_expr(target, bracket, index, value)
+            }
+            Expr::Tuple { paren, elements } => visitor.visit_tuple_expr(paren, elements),
+            Expr::Ternary { condition, question, then_branch, else_branch } => {
+                visitor.visit_ternary_expr(condition, question, then_branch, else_branch)
+            }
+            Expr::Unary { operator, right } => visitor.visit_unary_expr(operator, right),
+            Expr::Variable { name } => visitor.visit_variable_expr(name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Parsed programs are vectors of these and every pass walks them, so children are boxed to keep every node
+    // small and more of them in a cache line. The limit is a checkpoint: if a new variant makes this fail, box its
+    // biggest field instead of raising the limit. 96 bytes is what a call needs today: the Token of its closing
+    // paren, the boxed callee and the Vec of arguments
+    #[test]
+    fn expr_stays_small() {
+        assert!(std::mem::size_of::<Expr>() <= 96, "Expr is {} bytes", std::mem::size_of::<Expr>());
+    }
+}
@@ -0,0 +1,74 @@
+use crate::environment::Environment;
+use crate::errors::InterpreterRuntimeError;
+use crate::expressions::LiteralValue;
+use crate::interpreter::AstInterpreter;
+use crate::lexer::Token;
+use crate::statements::Stmt;
+
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+// A function declared in Wolff code, together with the scope it was declared in so it can see the variables around it
+pub struct Function {
+    // None for lambdas
+    pub name: Option<Token>,
+    pub params: Vec<Token>,
+    pub body: Vec<Stmt>,
+    pub closure: Rc<RefCell<Environment>>,
+}
+
+impl Function {
+    pub fn arity(&self) -> usize {
+        self.params.len()
+    }
+
+    pub fn name(&self) -> &str {
+        match &self.name {
+            Some(name) => &name.value,
+            None => "lambda",
+        }
+    }
+}
+
+// The closure can (indirectly) contain the function itself, so only the name is printed
+impl fmt::Debug for Function {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "<fn {}>", name.value),
+            None => write!(f, "<lambda>"),
+        }
+    }
+}
+
+// Signature of the Rust code behind a native function, it gets the arguments already evaluated and the
+// interpreter, so it can call back into Wolff code. It doesn't know where it was called from, so errors
+// it makes without a position (line 0) are moved to the call by the interpreter
+pub type NativeCallable = dyn Fn(&mut AstInterpreter, &[LiteralValue]) -> Result<LiteralValue, InterpreterRuntimeError>;
+
+// A function provided by the interpreter instead of being declared in Wolff code
+pub struct NativeFunction {
+    pub name: String,
+    pub arity: usize,
+    // The arity is only the least number of arguments, any after those are passed as well
+    pub variadic: bool,
+    pub function: Box<NativeCallable>,
+}
+
+impl fmt::Debug for NativeFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl fmt::Display for NativeFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<native fn {}>", self.name)
+    }
+}
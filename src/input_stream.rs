@@ -7,21 +7,25 @@ pub struct InputStream {
 }
 
 impl InputStream {
-    pub fn new(input: &String) -> Self {
+    // A byte order mark at the start (some Windows editors write one) isn't part of the code
+    pub fn new(input: &str) -> Self {
         InputStream {
             pos: 0,
-            line: 0,
-            col: 0,
-            input: input.to_owned()
+            line: 1,
+            col: 1,
+            input: input.strip_prefix('\u{FEFF}').unwrap_or(input).to_owned()
         }
     }
 
+    #[allow(clippy::should_implement_trait)]
     pub fn next(&mut self) -> char {
         let next_char = self.peek();
         self.pos += 1;
         if next_char == '\n' {
             self.line += 1;
-            self.col = 0;
+            self.col = 1;
+        } else if next_char == '\r' && self.get_char_at() == Some('\n') {
+            // The '\r' of a "\r\n" line ending is part of the line break, not a column
         } else {
             self.col += 1;
         }
@@ -33,12 +37,18 @@ impl InputStream {
         self.get_char_at().unwrap()
     }
 
+    // The character after the one peek() returns, if there is one
+    pub fn peek_next(&self) -> Option<char> {
+        self.input.chars().nth(self.pos + 1)
+    }
+
     pub fn eof(&self) -> bool {
         self.get_char_at().is_none()
     }
 
     pub fn get_current_line(&self) -> &str {
-        self.input.lines().nth(self.line).unwrap()
+        // Lines and columns are 1-based, but the iterator over the lines is not
+        self.input.lines().nth(self.line - 1).unwrap_or("")
     }
 
     fn get_char_at(&self) -> Option<char> {
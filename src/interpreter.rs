@@ -0,0 +1,1070 @@
+use crate::environment::Environment;
+use crate::errors::{ErrorCode, InterpreterRuntimeError};
+use crate::expressions::{Expr, ExprVisitor, LiteralValue};
+use crate::functions::{Function, NativeCallable, NativeFunction};
+use crate::lexer::{Token, TokenType};
+use crate::statements::{Stmt, StmtVisitor};
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Write};
+use std::rc::Rc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+// How many function calls can be running at once, a debug build runs out of native stack not far past twice that
+pub const MAX_CALL_DEPTH: usize = 512;
+
+// Why the execution of statements stopped early: either a runtime error, or a return/break/continue
+// unwinding through the enclosing blocks until the function call or loop that handles it
+pub enum InterpreterControl {
+    Error(InterpreterRuntimeError),
+    Return(LiteralValue),
+    Break,
+    Continue,
+}
+
+impl From<InterpreterRuntimeError> for InterpreterControl {
+    fn from(error: InterpreterRuntimeError) -> Self {
+        InterpreterControl::Error(error)
+    }
+}
+
+// Only nil and false are falsey, every other value (0 and "" included) is truthy
+fn is_truthy(value: &LiteralValue) -> bool {
+    !matches!(value, LiteralValue::Nil | LiteralValue::Bool(false))
+}
+
+fn check_arity(name: &str, arity: usize, arguments: usize) -> Result<(), InterpreterRuntimeError> {
+    if arguments == arity {
+        return Ok(());
+    }
+
+    Err(InterpreterRuntimeError {
+        code: ErrorCode::WrongArgumentCount,
+        message: format!("Expected {} arguments but got {} when calling '{}'", arity, arguments, name),
+        line: 0,
+        col: 0,
+    })
+}
+
+fn check_min_arity(name: &str, arity: usize, arguments: usize) -> Result<(), InterpreterRuntimeError> {
+    if arguments >= arity {
+        return Ok(());
+    }
+
+    Err(InterpreterRuntimeError {
+        code: ErrorCode::WrongArgumentCount,
+        message: format!("Expected at least {} arguments but got {} when calling '{}'", arity, arguments, name),
+        line: 0,
+        col: 0,
+    })
+}
+
+// Turn the value used as an index into a position in an array of the given length
+fn array_index(index: &LiteralValue, length: usize, bracket: &Token) -> Result<usize, InterpreterRuntimeError> {
+    let (code, message) = match index {
+        LiteralValue::Number(number) if number.fract() != 0.0 => {
+            (ErrorCode::InvalidArgument, format!("Array index must be a whole number, got {}", index))
+        }
+        LiteralValue::Number(number) if *number < 0.0 => {
+            (ErrorCode::IndexOutOfBounds, format!("Array index can't be negative, got {}", index))
+        }
+        LiteralValue::Number(number) if *number >= length as f64 => {
+            (ErrorCode::IndexOutOfBounds, format!("Index {} is out of bounds for an array of length {}", index, length))
+        }
+        LiteralValue::Number(number) => return Ok(*number as usize),
+        _ => (ErrorCode::TypeMismatch, format!("Array index must be a number, got {}", index)),
+    };
+
+    Err(InterpreterRuntimeError {
+        code,
+        message,
+        line: bracket.line,
+        col: bracket.col,
+    })
+}
+
+fn map_key<'a>(key: &'a LiteralValue, token: &Token) -> Result<&'a str, InterpreterRuntimeError> {
+    match key {
+        LiteralValue::Text(key) => Ok(key),
+        _ => Err(InterpreterRuntimeError {
+            code: ErrorCode::TypeMismatch,
+            message: format!("Map keys must be strings, got {}", key),
+            line: token.line,
+            col: token.col,
+        }),
+    }
+}
+
+fn not_indexable_error(bracket: &Token) -> InterpreterRuntimeError {
+    InterpreterRuntimeError {
+        code: ErrorCode::TypeMismatch,
+        message: "Only arrays, maps and byte strings can be indexed".to_string(),
+        line: bracket.line,
+        col: bracket.col,
+    }
+}
+
+// A number literal with an optional sign and exponent, e.g. `-1.5e3`. There are no hex or binary literals, so
+// `0x1F` isn't a number
+fn parse_number(text: &str) -> Option<f64> {
+    let unsigned = text.strip_prefix(['-', '+']).unwrap_or(text);
+    let (mantissa, exponent) = match unsigned.split_once(['e', 'E']) {
+        Some((mantissa, exponent)) => (mantissa, Some(exponent.strip_prefix(['-', '+']).unwrap_or(exponent))),
+        None => (unsigned, None),
+    };
+    let is_literal = mantissa.starts_with(|ch: char| ch.is_ascii_digit())
+        && mantissa.chars().all(|ch| ch.is_ascii_digit() || ch == '.')
+        && mantissa.matches('.').count() <= 1
+        && exponent.is_none_or(|exponent| !exponent.is_empty() && exponent.chars().all(|ch| ch.is_ascii_digit()));
+    if is_literal || unsigned == "inf" || unsigned == "nan" {
+        text.parse().ok()
+    } else {
+        None
+    }
+}
+
+// The defined name closest to a misspelled one, if any is close enough to be what was meant. That is one edit for
+// names of 2 to 5 characters and a third of the length for longer ones, one letter names get no suggestion since
+// any other one letter name would match. Ties go to the name that sorts first, so the suggestion doesn't depend
+// on the order of the scopes' HashMaps
+fn closest_name<'a>(name: &str, candidates: &'a [String]) -> Option<&'a str> {
+    let length = name.chars().count();
+    if length < 2 {
+        return None;
+    }
+    let max_distance = (length / 3).max(1);
+    candidates
+        .iter()
+        .map(|candidate| (edit_distance(name, candidate), candidate.as_str()))
+        .filter(|&(distance, _)| distance <= max_distance)
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
+// Levenshtein distance, the number of characters to insert, remove or replace to turn one string into the other
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let replace = previous[j] + usize::from(a_char != *b_char);
+            current.push(replace.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+// Fills in the placeholders of the template with the values, in order. A placeholder is `%`, optionally `-` to
+// pad on the right instead of the left, the least width, `.` and a precision, and then the type: `%d` is a whole
+// number, `%f` any number (with 6 decimals unless the precision says otherwise) and `%s` any value, cut to
+// the precision if there is one. `%%` is a '%'
+fn sprintf(template: &str, values: &[LiteralValue]) -> Result<String, InterpreterRuntimeError> {
+    let error = |code: ErrorCode, message: String| InterpreterRuntimeError { code, message, line: 0, col: 0 };
+
+    let mut output = String::new();
+    let mut values = values.iter();
+    let mut placeholders = 0;
+    let mut chars = template.chars().peekable();
+    while let Some(ch) = chars.next() {
+        if ch != '%' {
+            output.push(ch);
+            continue;
+        }
+        if chars.next_if_eq(&'%').is_some() {
+            output.push('%');
+            continue;
+        }
+
+        let left_aligned = chars.next_if_eq(&'-').is_some();
+        let mut width = String::new();
+        while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+            width.push(digit);
+        }
+        let mut precision = None;
+        if chars.next_if_eq(&'.').is_some() {
+            let mut digits = String::new();
+            while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+                digits.push(digit);
+            }
+            precision = Some(digits.parse::<usize>().unwrap_or(0));
+        }
+        let width = width.parse::<usize>().unwrap_or(0);
+
+        let conversion = chars.next();
+        placeholders += 1;
+        let value = match (conversion, values.next()) {
+            (Some('d' | 'f' | 's'), Some(value)) => value,
+            (Some('d' | 'f' | 's'), None) => {
+                let message = format!("sprintf is missing a value for placeholder {} of its template", placeholders);
+                return Err(error(ErrorCode::WrongArgumentCount, message));
+            }
+            (Some(conversion), _) => {
+                let message = format!("Unknown placeholder '%{}' in the template of sprintf", conversion);
+                return Err(error(ErrorCode::InvalidArgument, message));
+            }
+            (None, _) => {
+                let message = "Template of sprintf ends in the middle of a placeholder".to_string();
+                return Err(error(ErrorCode::InvalidArgument, message));
+            }
+        };
+
+        let text = match (conversion, value) {
+            (Some('d'), LiteralValue::Number(number)) if number.fract() == 0.0 => format!("{:.0}", number),
+            (Some('d'), value) => {
+                return Err(error(ErrorCode::TypeMismatch, format!("%d needs a whole number, got {}", value)));
+            }
+            (Some('f'), LiteralValue::Number(number)) => format!("{:.*}", precision.unwrap_or(6), number),
+            (Some('f'), value) => {
+                return Err(error(ErrorCode::TypeMismatch, format!("%f needs a number, got {}", value)));
+            }
+            (_, value) => match precision {
+                Some(precision) => value.to_string().chars().take(precision).collect(),
+                None => value.to_string(),
+            },
+        };
+
+        if left_aligned {
+            output.push_str(&format!("{:<width$}", text, width = width));
+        } else {
+            output.push_str(&format!("{:>width$}", text, width = width));
+        }
+    }
+
+    if values.len() > 0 {
+        let given = placeholders + values.len();
+        let message = format!("sprintf got {} values but its template has {} placeholders", given, placeholders);
+        return Err(error(ErrorCode::WrongArgumentCount, message));
+    }
+    Ok(output)
+}
+
+// Failing to write the output isn't tied to a place in the source
+fn output_error(error: io::Error) -> InterpreterRuntimeError {
+    InterpreterRuntimeError {
+        code: ErrorCode::OutputFailed,
+        message: format!("Can't write output: {}", error),
+        line: 0,
+        col: 0,
+    }
+}
+
+// Tree-walking interpreter, evaluates the AST directly without compiling it to bytecode first
+pub struct AstInterpreter {
+    globals: Rc<RefCell<Environment>>,
+    // Scope of the code being run right now, starts out as the globals
+    environment: Rc<RefCell<Environment>>,
+    // Written between the values of a print statement and after the last one, like Python's `sep` and `end`
+    print_separator: String,
+    print_terminator: String,
+    // Where print statements write to, shared with the natives that need it (e.g. flush)
+    output: Rc<RefCell<Box<dyn Write>>>,
+    // Where eprint and eprintln write to, kept apart from the output so diagnostics don't mix with the results
+    error_output: Rc<RefCell<Box<dyn Write>>>,
+    // How many function calls deep the interpreter is, return is only allowed when this isn't 0
+    function_depth: usize,
+    // Called with the name, old value and new value every time a var statement or an assignment runs
+    assignment_tracer: Option<Box<AssignmentTracer>>,
+    // Names of every native registered so far, the built-in ones included, reset keeps these
+    native_names: HashSet<String>,
+}
+
+// The old value is None when a var statement defines a name that wasn't in its scope yet
+pub type AssignmentTracer = dyn FnMut(&str, Option<&LiteralValue>, &LiteralValue);
+
+impl Default for AstInterpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AstInterpreter {
+    pub fn new() -> Self {
+        Self::with_output(Box::new(io::stdout()))
+    }
+
+    // Print statements write to the output instead of stdout, e.g. a buffer that a test reads back afterwards
+    pub fn with_output(output: Box<dyn Write>) -> Self {
+        let globals = Rc::new(RefCell::new(Environment::new()));
+        let mut interpreter = AstInterpreter {
+            environment: Rc::clone(&globals),
+            globals,
+            print_separator: String::from(" "),
+            print_terminator: String::from("\n"),
+            output: Rc::new(RefCell::new(output)),
+            error_output: Rc::new(RefCell::new(Box::new(io::stderr()))),
+            function_depth: 0,
+            assignment_tracer: None,
+            native_names: HashSet::new(),
+        };
+        interpreter.define_natives();
+        interpreter
+    }
+
+    // Forget everything the programs run so far defined, as if the interpreter was new. The output and print
+    // settings stay, and so do natives added with register_native, unless a program replaced them
+    pub fn reset(&mut self) {
+        let mut globals = Environment::new();
+        for (name, value) in self.globals.borrow().values() {
+            if matches!(value, LiteralValue::NativeFunction(_)) && self.native_names.contains(name) {
+                globals.define(name, value.clone());
+            }
+        }
+
+        self.globals = Rc::new(RefCell::new(globals));
+        self.environment = Rc::clone(&self.globals);
+        self.function_depth = 0;
+        // The built-in ones are defined again in case a program replaced them
+        self.define_natives();
+    }
+
+    fn define_natives(&mut self) {
+        // Seconds since the Unix epoch, with the fractional part
+        self.register_native("clock", 0, |_, _| {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+            Ok(LiteralValue::Number(now.as_secs_f64()))
+        });
+
+        self.register_native("len", 1, |_, arguments| {
+            let length = match &arguments[0] {
+                LiteralValue::Text(text) => text.chars().count(),
+                LiteralValue::Bytes(bytes) => bytes.len(),
+                LiteralValue::Array(elements) => elements.borrow().len(),
+                LiteralValue::Map(map) => map.borrow().len(),
+                LiteralValue::Tuple(elements) => elements.len(),
+                value => {
+                    return Err(InterpreterRuntimeError {
+                        code: ErrorCode::TypeMismatch,
+                        message: format!("Can't take the length of {}", value),
+                        line: 0,
+                        col: 0,
+                    })
+                }
+            };
+            Ok(LiteralValue::Number(length as f64))
+        });
+
+        // == compares numbers exactly, so `0.1 + 0.2 == 0.3` is false. This is true when the two numbers are at
+        // most epsilon apart
+        self.register_native("approx_eq", 3, |_, arguments| match arguments {
+            [LiteralValue::Number(a), LiteralValue::Number(b), LiteralValue::Number(epsilon)] if *epsilon >= 0.0 => {
+                Ok(LiteralValue::Bool((a - b).abs() <= *epsilon))
+            }
+            [LiteralValue::Number(_), LiteralValue::Number(_), LiteralValue::Number(epsilon)] => Err(InterpreterRuntimeError {
+                code: ErrorCode::InvalidArgument,
+                message: format!("Epsilon of approx_eq must be zero or more, got {}", LiteralValue::Number(*epsilon)),
+                line: 0,
+                col: 0,
+            }),
+            _ => Err(InterpreterRuntimeError {
+                code: ErrorCode::TypeMismatch,
+                message: "Arguments of approx_eq must be numbers".to_string(),
+                line: 0,
+                col: 0,
+            }),
+        });
+
+        // Reads a number written the way the lexer reads them (digits with at most one '.'), with an optional
+        // sign, or inf and nan. Anything else gives nil, so a program can check the input it got
+        self.register_native("number", 1, |_, arguments| match &arguments[0] {
+            LiteralValue::Text(text) => Ok(parse_number(text.trim()).map_or(LiteralValue::Nil, LiteralValue::Number)),
+            value => Err(InterpreterRuntimeError {
+                code: ErrorCode::TypeMismatch,
+                message: format!("Argument of number must be a string, got {}", value),
+                line: 0,
+                col: 0,
+            }),
+        });
+
+        // printf-style formatting, e.g. `sprintf("%5.2f %s", 3.14159, "pi")`
+        self.register_variadic_native("sprintf", 1, |_, arguments| match &arguments[0] {
+            LiteralValue::Text(template) => sprintf(template, &arguments[1..]).map(LiteralValue::Text),
+            value => Err(InterpreterRuntimeError {
+                code: ErrorCode::TypeMismatch,
+                message: format!("Template of sprintf must be a string, got {}", value),
+                line: 0,
+                col: 0,
+            }),
+        });
+
+        self.register_native("str", 1, |_, arguments| Ok(LiteralValue::Text(arguments[0].to_string())));
+        self.register_native("repr", 1, |_, arguments| Ok(LiteralValue::Text(arguments[0].repr())));
+
+        // Output is only flushed at the end of interpret(), or when a print ends in a newline and the sink is
+        // line buffered, so a prompt printed without a terminator needs this to show up before the program waits
+        let output = Rc::clone(&self.output);
+        self.register_native("flush", 0, move |_, _| match output.borrow_mut().flush() {
+            Ok(()) => Ok(LiteralValue::Nil),
+            Err(e) => Err(output_error(e)),
+        });
+
+        // Same as print with a single value, but to the error output. The output is flushed first, so when both go
+        // to the same terminal the lines show up in the order the program wrote them
+        for (name, terminator) in [("eprint", ""), ("eprintln", "\n")] {
+            let output = Rc::clone(&self.output);
+            let error_output = Rc::clone(&self.error_output);
+            self.register_native(name, 1, move |_, arguments| {
+                output.borrow_mut().flush().map_err(output_error)?;
+                let mut error_output = error_output.borrow_mut();
+                write!(error_output, "{}{}", arguments[0], terminator).map_err(output_error)?;
+                error_output.flush().map_err(output_error)?;
+                Ok(LiteralValue::Nil)
+            });
+        }
+
+        // Calls the function it's given without arguments and returns how long that took, in milliseconds
+        self.register_native("time", 1, |interpreter, arguments| {
+            let start = Instant::now();
+            interpreter.call_value(&arguments[0], Vec::new())?;
+            Ok(LiteralValue::Number(start.elapsed().as_secs_f64() * 1000.0))
+        });
+    }
+
+    // Define a global function implemented in Rust, calling it with a different number of arguments than
+    // the arity is a runtime error, so the function can index into the arguments without checking
+    pub fn register_native<F>(&mut self, name: &str, arity: usize, function: F)
+    where
+        F: Fn(&mut AstInterpreter, &[LiteralValue]) -> Result<LiteralValue, InterpreterRuntimeError> + 'static,
+    {
+        self.define_native(name, arity, false, Box::new(function));
+    }
+
+    // Same as register_native, but the function takes at least min_arity arguments instead of exactly that many
+    pub fn register_variadic_native<F>(&mut self, name: &str, min_arity: usize, function: F)
+    where
+        F: Fn(&mut AstInterpreter, &[LiteralValue]) -> Result<LiteralValue, InterpreterRuntimeError> + 'static,
+    {
+        self.define_native(name, min_arity, true, Box::new(function));
+    }
+
+    fn define_native(&mut self, name: &str, arity: usize, variadic: bool, function: Box<NativeCallable>) {
+        let native = NativeFunction {
+            name: name.to_string(),
+            arity,
+            variadic,
+            function,
+        };
+        self.globals.borrow_mut().define(name, LiteralValue::NativeFunction(Rc::new(native)));
+        self.native_names.insert(name.to_string());
+    }
+
+    // Send the output of print statements somewhere other than stdout, whatever was written so far is flushed first
+    pub fn set_output(&mut self, output: Box<dyn Write>) {
+        let mut current = self.output.borrow_mut();
+        let _ = current.flush();
+        *current = output;
+    }
+
+    // Watch every variable the programs define or assign to, e.g. for a debugger. Kept across reset like the output
+    pub fn set_assignment_tracer(&mut self, tracer: Box<AssignmentTracer>) {
+        self.assignment_tracer = Some(tracer);
+    }
+
+    fn trace_assignment(&mut self, name: &str, old_value: Option<&LiteralValue>, new_value: &LiteralValue) {
+        if let Some(tracer) = &mut self.assignment_tracer {
+            tracer(name, old_value, new_value);
+        }
+    }
+
+    // Same as set_output, for what eprint and eprintln write
+    pub fn set_error_output(&mut self, error_output: Box<dyn Write>) {
+        let mut current = self.error_output.borrow_mut();
+        let _ = current.flush();
+        *current = error_output;
+    }
+
+    pub fn set_print_separator(&mut self, separator: &str) {
+        self.print_separator = separator.to_string();
+    }
+
+    pub fn set_print_terminator(&mut self, terminator: &str) {
+        self.print_terminator = terminator.to_string();
+    }
+
+    // Outermost scope, where everything defined at the top level of the program lives
+    pub fn globals(&self) -> Rc<RefCell<Environment>> {
+        Rc::clone(&self.globals)
+    }
+
+    pub fn interpret(&mut self, statements: &[Stmt]) -> Result<(), InterpreterRuntimeError> {
+        self.hoist_functions(statements);
+
+        let mut result = Ok(());
+        for statement in statements {
+            match self.execute(statement) {
+                Ok(()) => {}
+                Err(InterpreterControl::Error(e)) => {
+                    result = Err(e);
+                    break;
+                }
+                // Return is refused outside of a function and the parser only allows break and continue
+                // inside loops, so these never reach the top level
+                Err(_) => break,
+            }
+        }
+
+        // Whatever the program printed must be visible once it's done, even if it stopped on an error
+        let flushed = self.output.borrow_mut().flush();
+        result?;
+        flushed.map_err(output_error)
+    }
+
+    // Same as interpret, for a single expression whose value is wanted
+    pub fn interpret_expression(&mut self, expression: &Expr) -> Result<LiteralValue, InterpreterRuntimeError> {
+        let result = self.evaluate(expression);
+        let flushed = self.output.borrow_mut().flush();
+        let value = result?;
+        flushed.map_err(output_error)?;
+        Ok(value)
+    }
+
+    // A function can be called anywhere in the block it is declared in, even before its declaration, so the
+    // functions of a block are defined before any of its statements run. Other functions declared in the same
+    // block are visible from its body the same way, which is what lets two functions call each other.
+    // The declaration itself still runs in order and defines the function again if something else took its name
+    // in between, so if a variable with the same name is declared before it, it holds the variable until the
+    // declaration is reached.
+    // Blocks only hoist into their own scope, so a function declared in a block is local to it like a variable
+    // would be, and shadows any function with the same name outside of the block until the block ends
+    fn hoist_functions(&mut self, statements: &[Stmt]) {
+        for statement in statements {
+            if let Stmt::Function { name, params, body } = statement {
+                self.define_function(name, params, body);
+            }
+        }
+    }
+
+    fn define_function(&mut self, name: &Token, params: &[Token], body: &[Stmt]) {
+        let function = Function {
+            name: Some(name.clone()),
+            params: params.to_vec(),
+            body: body.to_vec(),
+            closure: Rc::clone(&self.environment),
+        };
+        self.environment.borrow_mut().define(&name.value, LiteralValue::Function(Rc::new(function)));
+    }
+
+    fn execute(&mut self, statement: &Stmt) -> Result<(), InterpreterControl> {
+        statement.accept(self)
+    }
+
+    fn evaluate(&mut self, expression: &Expr) -> Result<LiteralValue, InterpreterRuntimeError> {
+        expression.accept(self)
+    }
+
+    // The condition of an if or a while, an error in it is reported at the keyword of the statement it stopped
+    fn condition(&mut self, keyword: &Token, condition: &Expr) -> Result<bool, InterpreterRuntimeError> {
+        match self.evaluate(condition) {
+            Ok(value) => Ok(is_truthy(&value)),
+            Err(e) => Err(InterpreterRuntimeError {
+                line: keyword.line,
+                col: keyword.col,
+                ..e
+            }),
+        }
+    }
+
+    // Call any callable value, the errors about the call itself (not a function, wrong arity) have no position
+    fn call_value(&mut self, callee: &LiteralValue, arguments: Vec<LiteralValue>) -> Result<LiteralValue, InterpreterRuntimeError> {
+        match callee {
+            LiteralValue::Function(function) => {
+                check_arity(function.name(), function.arity(), arguments.len())?;
+                self.call_function(function, arguments)
+            }
+            LiteralValue::NativeFunction(function) => {
+                if function.variadic {
+                    check_min_arity(&function.name, function.arity, arguments.len())?;
+                } else {
+                    check_arity(&function.name, function.arity, arguments.len())?;
+                }
+                (function.function)(self, &arguments)
+            }
+            _ => Err(InterpreterRuntimeError {
+                code: ErrorCode::NotCallable,
+                message: "Can only call functions".to_string(),
+                line: 0,
+                col: 0,
+            }),
+        }
+    }
+
+    fn call_function(&mut self, function: &Function, arguments: Vec<LiteralValue>) -> Result<LiteralValue, InterpreterRuntimeError> {
+        // Reported at the call, before the native stack runs out
+        if self.function_depth >= MAX_CALL_DEPTH {
+            return Err(InterpreterRuntimeError {
+                code: ErrorCode::StackOverflow,
+                message: "Stack overflow".to_string(),
+                line: 0,
+                col: 0,
+            });
+        }
+
+        // Parameters live in a fresh scope on top of the one the function was declared in
+        let mut environment = Environment::from_enclosing(Rc::clone(&function.closure));
+        for (param, argument) in function.params.iter().zip(arguments) {
+            environment.define(&param.value, argument);
+        }
+
+        self.function_depth += 1;
+        let result = self.execute_block(&function.body, environment);
+        self.function_depth -= 1;
+
+        match result {
+            Ok(()) => Ok(LiteralValue::Nil),
+            Err(InterpreterControl::Return(value)) => Ok(value),
+            Err(InterpreterControl::Error(e)) => Err(e),
+            // The parser doesn't let break and continue escape a function body
+            Err(InterpreterControl::Break | InterpreterControl::Continue) => Ok(LiteralValue::Nil),
+        }
+    }
+
+    // Run the statements inside the given environment, then restore the previous one even if one of them failed
+    fn execute_block(&mut self, statements: &[Stmt], environment: Environment) -> Result<(), InterpreterControl> {
+        let previous = std::mem::replace(&mut self.environment, Rc::new(RefCell::new(environment)));
+        self.hoist_functions(statements);
+
+        let mut result = Ok(());
+        for statement in statements {
+            result = self.execute(statement);
+            if result.is_err() {
+                break;
+            }
+        }
+
+        self.environment = previous;
+        result
+    }
+}
+
+fn number_operands_error(operator: &Token) -> InterpreterRuntimeError {
+    InterpreterRuntimeError {
+        code: ErrorCode::TypeMismatch,
+        message: format!("Operands of '{}' must be numbers", operator.value),
+        line: operator.line,
+        col: operator.col,
+    }
+}
+
+// Shifts only make sense on whole numbers, which are shifted as 64 bit integers. The shift amount has to be
+// at least 0 and less than 64, the bits shifted out on either side are lost
+fn shift(lhs: f64, rhs: f64, operator: &Token) -> Result<LiteralValue, InterpreterRuntimeError> {
+    let error = |code: ErrorCode, message: String| InterpreterRuntimeError {
+        code,
+        message,
+        line: operator.line,
+        col: operator.col,
+    };
+
+    let is_integer = |number: f64| number.fract() == 0.0 && number >= i64::MIN as f64 && number < i64::MAX as f64;
+    if !is_integer(lhs) || !is_integer(rhs) {
+        return Err(error(ErrorCode::TypeMismatch, format!("Operands of '{}' must be integers", operator.value)));
+    }
+    if !(0.0..64.0).contains(&rhs) {
+        let message = format!("Can't shift by {}, the shift amount must be between 0 and 63", rhs);
+        return Err(error(ErrorCode::InvalidArgument, message));
+    }
+
+    let (lhs, rhs) = (lhs as i64, rhs as u32);
+    let result = match operator.token_type {
+        TokenType::LessLess => lhs << rhs,
+        _ => lhs >> rhs,
+    };
+    Ok(LiteralValue::Number(result as f64))
+}
+
+impl ExprVisitor<Result<LiteralValue, InterpreterRuntimeError>> for AstInterpreter {
+    fn visit_array_literal_expr(&mut self, _bracket: &Token, elements: &[Expr]) -> Result<LiteralValue, InterpreterRuntimeError> {
+        let mut values = Vec::with_capacity(elements.len());
+        for element in elements {
+            values.push(self.evaluate(element)?);
+        }
+        Ok(LiteralValue::Array(Rc::new(RefCell::new(values))))
+    }
+
+    fn visit_assign_expr(&mut self, name: &Token, value: &Expr) -> Result<LiteralValue, InterpreterRuntimeError> {
+        let value = self.evaluate(value)?;
+        let old_value = self.environment.borrow_mut().assign(name, value.clone())?;
+        self.trace_assignment(&name.value, Some(&old_value), &value);
+        Ok(value)
+    }
+
+    fn visit_binary_expr(&mut self, left: &Expr, operator: &Token, right: &Expr) -> Result<LiteralValue, InterpreterRuntimeError> {
+        let lhs = self.evaluate(left)?;
+        let rhs = self.evaluate(right)?;
+
+        match operator.token_type {
+            TokenType::EqualEqual => return Ok(LiteralValue::Bool(lhs == rhs)),
+            TokenType::BangEqual => return Ok(LiteralValue::Bool(lhs != rhs)),
+            TokenType::Plus => {
+                if let (LiteralValue::Text(lhs), LiteralValue::Text(rhs)) = (&lhs, &rhs) {
+                    return Ok(LiteralValue::Text(format!("{}{}", lhs, rhs)));
+                }
+            }
+            _ => {}
+        }
+
+        // Everything else only works on numbers
+        let (lhs, rhs) = match (lhs, rhs) {
+            (LiteralValue::Number(lhs), LiteralValue::Number(rhs)) => (lhs, rhs),
+            _ => return Err(number_operands_error(operator)),
+        };
+
+        match operator.token_type {
+            TokenType::Plus => Ok(LiteralValue::Number(lhs + rhs)),
+            TokenType::Minus => Ok(LiteralValue::Number(lhs - rhs)),
+            TokenType::Star => Ok(LiteralValue::Number(lhs * rhs)),
+            TokenType::Slash => Ok(LiteralValue::Number(lhs / rhs)),
+            // The result has the sign of the left operand, like in Rust and C
+            TokenType::Percent => Ok(LiteralValue::Number(lhs % rhs)),
+            TokenType::Greater => Ok(LiteralValue::Bool(lhs > rhs)),
+            TokenType::GreaterEqual => Ok(LiteralValue::Bool(lhs >= rhs)),
+            TokenType::Less => Ok(LiteralValue::Bool(lhs < rhs)),
+            TokenType::LessEqual => Ok(LiteralValue::Bool(lhs <= rhs)),
+            TokenType::LessLess | TokenType::GreaterGreater => shift(lhs, rhs, operator),
+            _ => Err(InterpreterRuntimeError {
+                code: ErrorCode::InvalidOperator,
+                message: format!("Invalid binary operator '{}'", operator.value),
+                line: operator.line,
+                col: operator.col,
+            }),
+        }
+    }
+
+    fn visit_call_expr(&mut self, callee: &Expr, paren: &Token, arguments: &[Expr]) -> Result<LiteralValue, InterpreterRuntimeError> {
+        let callee = self.evaluate(callee)?;
+
+        let mut argument_values = Vec::with_capacity(arguments.len());
+        for argument in arguments {
+            argument_values.push(self.evaluate(argument)?);
+        }
+
+        // Errors from the function's own code already point inside it, the rest are reported at the call
+        self.call_value(&callee, argument_values).map_err(|e| match e.line {
+            0 => InterpreterRuntimeError {
+                line: paren.line,
+                col: paren.col,
+                ..e
+            },
+            _ => e,
+        })
+    }
+
+    fn visit_grouping_expr(&mut self, expression: &Expr) -> Result<LiteralValue, InterpreterRuntimeError> {
+        self.evaluate(expression)
+    }
+
+    fn visit_interpolation_expr(&mut self, _string: &Token, parts: &[Expr]) -> Result<LiteralValue, InterpreterRuntimeError> {
+        let mut text = String::new();
+        for part in parts {
+            text.push_str(&self.evaluate(part)?.to_string());
+        }
+        Ok(LiteralValue::Text(text))
+    }
+
+    fn visit_lambda_expr(&mut self, params: &[Token], body: &[Stmt]) -> Result<LiteralValue, InterpreterRuntimeError> {
+        let function = Function {
+            name: None,
+            params: params.to_vec(),
+            body: body.to_vec(),
+            closure: Rc::clone(&self.environment),
+        };
+        Ok(LiteralValue::Function(Rc::new(function)))
+    }
+
+    fn visit_literal_expr(&mut self, value: &LiteralValue, _token: Option<&Token>) -> Result<LiteralValue, InterpreterRuntimeError> {
+        Ok(value.clone())
+    }
+
+    // Later entries win when the same key is written twice
+    fn visit_map_literal_expr(&mut self, brace: &Token, entries: &[(Expr, Expr)]) -> Result<LiteralValue, InterpreterRuntimeError> {
+        let mut map = HashMap::with_capacity(entries.len());
+        for (key, value) in entries {
+            let key = self.evaluate(key)?;
+            let key = map_key(&key, brace)?.to_string();
+            map.insert(key, self.evaluate(value)?);
+        }
+        Ok(LiteralValue::Map(Rc::new(RefCell::new(map))))
+    }
+
+    fn visit_logical_expr(&mut self, left: &Expr, operator: &Token, right: &Expr) -> Result<LiteralValue, InterpreterRuntimeError> {
+        let lhs = self.evaluate(left)?;
+
+        // The result is the operand that decided it, not a boolean, so `nil or "x"` is "x" and `1 and 2` is 2.
+        // The right side is only evaluated if the left one doesn't decide the result on its own
+        if operator.token_type.is_keyword("or") {
+            if is_truthy(&lhs) {
+                return Ok(lhs);
+            }
+        } else if operator.token_type.is_keyword("and") {
+            if !is_truthy(&lhs) {
+                return Ok(lhs);
+            }
+        } else {
+            return Err(InterpreterRuntimeError {
+                code: ErrorCode::InvalidOperator,
+                message: format!("Invalid logical operator '{}'", operator.value),
+                line: operator.line,
+                col: operator.col,
+            });
+        }
+
+        self.evaluate(right)
+    }
+
+    // Reading a key that isn't in a map gives nil
+    fn visit_index_expr(&mut self, target: &Expr, bracket: &Token, index: &Expr) -> Result<LiteralValue, InterpreterRuntimeError> {
+        let target = self.evaluate(target)?;
+        let index = self.evaluate(index)?;
+
+        match target {
+            LiteralValue::Array(array) => {
+                let elements = array.borrow();
+                let index = array_index(&index, elements.len(), bracket)?;
+                Ok(elements[index].clone())
+            }
+            LiteralValue::Map(map) => {
+                let key = map_key(&index, bracket)?;
+                Ok(map.borrow().get(key).cloned().unwrap_or(LiteralValue::Nil))
+            }
+            LiteralValue::Bytes(bytes) => {
+                let index = array_index(&index, bytes.len(), bracket)?;
+                Ok(LiteralValue::Number(bytes[index] as f64))
+            }
+            _ => Err(not_indexable_error(bracket)),
+        }
+    }
+
+    // Assigning to a key that isn't in a map adds it, arrays never grow this way
+    fn visit_index_assign_expr(&mut self, target: &Expr, bracket: &Token, index: &Expr, value: &Expr) -> Result<LiteralValue, InterpreterRuntimeError> {
+        let target = self.evaluate(target)?;
+        let index = self.evaluate(index)?;
+        let value = self.evaluate(value)?;
+
+        match target {
+            LiteralValue::Array(array) => {
+                let mut elements = array.borrow_mut();
+                let index = array_index(&index, elements.len(), bracket)?;
+                elements[index] = value.clone();
+            }
+            LiteralValue::Map(map) => {
+                let key = map_key(&index, bracket)?;
+                map.borrow_mut().insert(key.to_string(), value.clone());
+            }
+            // Like strings, byte strings are values and never change once they are made
+            LiteralValue::Bytes(_) => {
+                return Err(InterpreterRuntimeError {
+                    code: ErrorCode::ImmutableValue,
+                    message: "Byte strings can't be changed".to_string(),
+                    line: bracket.line,
+                    col: bracket.col,
+                })
+            }
+            _ => return Err(not_indexable_error(bracket)),
+        }
+        Ok(value)
+    }
+
+    fn visit_tuple_expr(&mut self, _paren: &Token, elements: &[Expr]) -> Result<LiteralValue, InterpreterRuntimeError> {
+        let mut values = Vec::with_capacity(elements.len());
+        for element in elements {
+            values.push(self.evaluate(element)?);
+        }
+        Ok(LiteralValue::Tuple(values))
+    }
+
+    // Only the branch that was picked is evaluated
+    fn visit_ternary_expr(&mut self, condition: &Expr, _question: &Token, then_branch: &Expr, else_branch: &Expr) -> Result<LiteralValue, InterpreterRuntimeError> {
+        if is_truthy(&self.evaluate(condition)?) {
+            self.evaluate(then_branch)
+        } else {
+            self.evaluate(else_branch)
+        }
+    }
+
+    fn visit_unary_expr(&mut self, operator: &Token, right: &Expr) -> Result<LiteralValue, InterpreterRuntimeError> {
+        let rhs = self.evaluate(right)?;
+
+        match (&operator.token_type, rhs) {
+            (TokenType::Minus, LiteralValue::Number(value)) => Ok(LiteralValue::Number(-value)),
+            // Same rule as conditions, so `!x` is true exactly when `if x` wouldn't run
+            (TokenType::Bang, value) => Ok(LiteralValue::Bool(!is_truthy(&value))),
+            _ => Err(InterpreterRuntimeError {
+                code: ErrorCode::TypeMismatch,
+                message: "Operand of '-' must be a number".to_string(),
+                line: operator.line,
+                col: operator.col,
+            }),
+        }
+    }
+
+    fn visit_variable_expr(&mut self, name: &Token) -> Result<LiteralValue, InterpreterRuntimeError> {
+        let environment = self.environment.borrow();
+        environment.get(name).map_err(|mut error| {
+            if let Some(suggestion) = closest_name(&name.value, &environment.visible_names()) {
+                error.message = format!("{}, did you mean '{}'?", error.message, suggestion);
+            }
+            error
+        })
+    }
+}
+
+impl StmtVisitor<Result<(), InterpreterControl>> for AstInterpreter {
+    fn visit_block_stmt(&mut self, statements: &[Stmt]) -> Result<(), InterpreterControl> {
+        let environment = Environment::from_enclosing(Rc::clone(&self.environment));
+        self.execute_block(statements, environment)
+    }
+
+    fn visit_break_stmt(&mut self, _keyword: &Token) -> Result<(), InterpreterControl> {
+        Err(InterpreterControl::Break)
+    }
+
+    fn visit_continue_stmt(&mut self, _keyword: &Token) -> Result<(), InterpreterControl> {
+        Err(InterpreterControl::Continue)
+    }
+
+    fn visit_expression_stmt(&mut self, expression: &Expr) -> Result<(), InterpreterControl> {
+        self.evaluate(expression)?;
+        Ok(())
+    }
+
+    // If the hoisted function is still there it is kept, so that it stays equal to references taken before
+    fn visit_function_stmt(&mut self, name: &Token, params: &[Token], body: &[Stmt]) -> Result<(), InterpreterControl> {
+        let hoisted = match self.environment.borrow().values().get(&name.value) {
+            Some(LiteralValue::Function(function)) => {
+                let same_declaration = function.name.as_ref().is_some_and(|declared| (declared.line, declared.col) == (name.line, name.col));
+                same_declaration && Rc::ptr_eq(&function.closure, &self.environment)
+            }
+            _ => false,
+        };
+        if !hoisted {
+            self.define_function(name, params, body);
+        }
+        Ok(())
+    }
+
+    fn visit_if_stmt(&mut self, keyword: &Token, condition: &Expr, then_branch: &Stmt, else_branch: Option<&Stmt>) -> Result<(), InterpreterControl> {
+        let condition = self.condition(keyword, condition)?;
+        match else_branch {
+            _ if condition => self.execute(then_branch),
+            Some(else_branch) => self.execute(else_branch),
+            None => Ok(()),
+        }
+    }
+
+    fn visit_print_stmt(&mut self, _keyword: &Token, expressions: &[Expr]) -> Result<(), InterpreterControl> {
+        let mut values = Vec::with_capacity(expressions.len());
+        for expression in expressions {
+            values.push(self.evaluate(expression)?.to_string());
+        }
+        write!(self.output.borrow_mut(), "{}{}", values.join(&self.print_separator), self.print_terminator)
+            .map_err(|e| output_error(e).into())
+    }
+
+    fn visit_return_stmt(&mut self, keyword: &Token, value: Option<&Expr>) -> Result<(), InterpreterControl> {
+        if self.function_depth == 0 {
+            return Err(InterpreterRuntimeError {
+                code: ErrorCode::ReturnOutsideFunction,
+                message: "Can't return from outside a function".to_string(),
+                line: keyword.line,
+                col: keyword.col,
+            }
+            .into());
+        }
+
+        let value = match value {
+            Some(value) => self.evaluate(value)?,
+            None => LiteralValue::Nil,
+        };
+        Err(InterpreterControl::Return(value))
+    }
+
+    fn visit_var_stmt(&mut self, name: &Token, initializer: &Expr) -> Result<(), InterpreterControl> {
+        let value = self.evaluate(initializer)?;
+        // Only copied when someone is watching
+        let traced_value = self.assignment_tracer.is_some().then(|| value.clone());
+        let old_value = self.environment.borrow_mut().define(&name.value, value);
+        if let Some(value) = traced_value {
+            self.trace_assignment(&name.value, old_value.as_ref(), &value);
+        }
+        Ok(())
+    }
+
+    fn visit_while_stmt(&mut self, keyword: &Token, condition: &Expr, body: &Stmt, increment: Option<&Expr>) -> Result<(), InterpreterControl> {
+        while self.condition(keyword, condition)? {
+            match self.execute(body) {
+                Ok(()) | Err(InterpreterControl::Continue) => {}
+                Err(InterpreterControl::Break) => return Ok(()),
+                Err(e) => return Err(e),
+            }
+
+            if let Some(increment) = increment {
+                self.evaluate(increment)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input_stream::InputStream;
+    use crate::lexer::TokenStream;
+    use crate::parser::Parser;
+
+    // Keeps what the interpreter prints, the test still holds a clone after the interpreter took its own
+    #[derive(Clone, Default)]
+    struct SharedOutput(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedOutput {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // What the code printed, or the runtime error it stopped with
+    fn run(source: &str) -> Result<String, InterpreterRuntimeError> {
+        let mut input_stream = InputStream::new(source);
+        let tokens: Vec<Token> = TokenStream::new(&mut input_stream).collect::<Result<_, _>>().unwrap();
+        let statements = Parser::new(&tokens).parse_program().unwrap();
+
+        let output = SharedOutput::default();
+        AstInterpreter::with_output(Box::new(output.clone())).interpret(&statements)?;
+        let printed = output.0.borrow().clone();
+        Ok(String::from_utf8(printed).unwrap())
+    }
+
+    #[test]
+    fn while_condition_error_is_reported_at_the_keyword() {
+        let error = run("var i = 0;\n  while (i < \"a\") { i = i + 1; }").unwrap_err();
+        assert_eq!(error.code, ErrorCode::TypeMismatch);
+        assert_eq!((error.line, error.col), (2, 3));
+    }
+
+    #[test]
+    fn print_separates_values_with_one_space() {
+        assert_eq!(run("print 1;").unwrap(), "1\n");
+        assert_eq!(run("var x = 3; print \"x =\", x, true;").unwrap(), "x = 3 true\n");
+        // The space only goes between values, an empty string still gets one on each side
+        assert_eq!(run("print \"a\", \"\", \"b\";").unwrap(), "a  b\n");
+        assert_eq!(run("print \" a \", nil;").unwrap(), " a  nil\n");
+    }
+
+    #[test]
+    fn number_parses_what_the_lexer_does_plus_exponents() {
+        assert_eq!(run("print number(\"42\"), number(\" -1.5 \");").unwrap(), "42 -1.5\n");
+        assert_eq!(run("print number(\"1e3\"), number(\"2.5E-2\"), number(\"-1e+2\");").unwrap(), "1000 0.025 -100\n");
+        assert_eq!(run("print number(\"inf\"), number(\"-inf\");").unwrap(), "inf -inf\n");
+        // Hex isn't a literal the lexer knows
+        assert_eq!(run("print number(\"0x1F\");").unwrap(), "nil\n");
+        for text in ["abc", "", "1e", "e3", "1e3.5", "1.2.3", "1e--3", "infinity"] {
+            assert_eq!(run(&format!("print number(\"{}\");", text)).unwrap(), "nil\n", "number(\"{}\")", text);
+        }
+    }
+}
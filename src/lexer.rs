@@ -1,22 +1,34 @@
-use crate::errors::InvalidTokenError;
+use crate::errors::{ErrorCode, InvalidTokenError};
 use crate::input_stream::InputStream;
 
 use regex::Regex;
+use std::collections::{HashSet, VecDeque};
 use std::fmt;
+use std::sync::LazyLock;
 
-const KEYWORDS: &'static [&'static str] = &[
-    "if", "else", "lambda", "λ", "true", "false", "while", "loop", "for", "return", "let", "nil", "and", "or", "struct", "this"
+const KEYWORDS: &[&str] = &[
+    "if", "else", "elif", "lambda", "λ", "true", "false", "while", "loop", "for", "break", "continue", "return", "let", "var", "fun", "print", "nil", "and", "or", "struct", "this"
 ];
-const PUNCTS: &'static [char] = &['(', ')', '{', '}', ',', '.', '-', '+', ';', '+', '-', '*', '/', '%', '=', '&', '|', '^', '<', '>', '!'];
+// Built once on first use, so the order of KEYWORDS doesn't matter
+static KEYWORD_SET: LazyLock<HashSet<&'static str>> = LazyLock::new(|| KEYWORDS.iter().copied().collect());
+// Identifiers and number literals longer than this are rejected instead of being read into memory
+pub const DEFAULT_MAX_TOKEN_LENGTH: usize = 1024;
+const PUNCTS: &[char] = &['(', ')', '{', '}', '[', ']', ',', '.', '?', ':', '-', '+', ';', '+', '-', '*', '/', '%', '=', '&', '|', '^', '<', '>', '!'];
 
 pub struct TokenStream {
     input: InputStream,
     current: Token,
     has_started: bool,
+    // Tokens (or errors) that were lexed ahead by peek_n but not yet handed out by next
+    lookahead: VecDeque<Result<Token, InvalidTokenError>>,
+    max_token_length: usize,
     pub has_error: bool,
+    // Where the token being read starts, every token and most errors are reported at their first character
+    start_line: usize,
+    start_col: usize,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct Token {
     pub token_type: TokenType,
     pub value: String,
@@ -24,20 +36,25 @@ pub struct Token {
     pub col: usize,
 }
 
-#[derive(Clone, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Default)]
 pub enum TokenType {
     // Single character tokens
     LeftParen,
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
     Comma,
     Dot,
+    Question,
+    Colon,
     Minus,
     Plus,
     Semicolon,
     Slash,
     Star,
+    Percent,
     // One or two character tokens
     Bang,
     BangEqual,
@@ -47,14 +64,20 @@ pub enum TokenType {
     GreaterEqual,
     Less,
     LessEqual,
+    LessLess,
+    GreaterGreater,
     // Literals
     Identifier,
     String,
+    // A string with `${...}` in it, its value is the string as it was written
+    Interpolation,
+    Bytes,
     Integer,
     Numeral,
     // Keywords
-    Keyword,
+    Keyword(String),
     // EOF token
+    #[default]
     Eof,
 }
 
@@ -66,13 +89,18 @@ impl fmt::Display for TokenType {
             | TokenType::RightParen
             | TokenType::LeftBrace
             | TokenType::RightBrace
+            | TokenType::LeftBracket
+            | TokenType::RightBracket
             | TokenType::Comma
             | TokenType::Dot
+            | TokenType::Question
+            | TokenType::Colon
             | TokenType::Minus
             | TokenType::Plus
             | TokenType::Semicolon
             | TokenType::Slash
-            | TokenType::Star => "Single punctuation",
+            | TokenType::Star
+            | TokenType::Percent => "Single punctuation",
             // One or two character tokens
             TokenType::BangEqual
             | TokenType::Bang
@@ -81,14 +109,18 @@ impl fmt::Display for TokenType {
             | TokenType::Greater
             | TokenType::GreaterEqual
             | TokenType::Less
-            | TokenType::LessEqual => "Punctuation",
+            | TokenType::LessEqual
+            | TokenType::LessLess
+            | TokenType::GreaterGreater => "Punctuation",
             // Literals
             TokenType::Integer => "Integer",
             TokenType::Numeral => "Numeral",
             TokenType::String => "String",
+            TokenType::Interpolation => "Interpolated string",
+            TokenType::Bytes => "Bytes",
             TokenType::Identifier => "Identifier",
             // Keywords
-            TokenType::Keyword => "Keyword",
+            TokenType::Keyword(_) => "Keyword",
             // EOF token
             TokenType::Eof => "EOF",
         };
@@ -96,14 +128,15 @@ impl fmt::Display for TokenType {
     }
 }
 
-impl Default for TokenType {
-    fn default() -> Self {
-        TokenType::Eof
+impl TokenType {
+    // Compares against a keyword without building a TokenType::Keyword (and its String) to compare with
+    pub fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self, TokenType::Keyword(current_keyword) if current_keyword == keyword)
     }
 }
 
 impl Token {
-    pub fn new(token_type: TokenType, value: &String, line: usize, col: usize) -> Self {
+    pub fn new(token_type: TokenType, value: &str, line: usize, col: usize) -> Self {
         Token {
             token_type,
             value: value.to_owned(),
@@ -119,17 +152,26 @@ impl TokenStream {
             input: input.clone(),
             current: Token::new(TokenType::default(), &String::default(), 0, 0),
             has_started: false,
+            lookahead: VecDeque::new(),
+            max_token_length: DEFAULT_MAX_TOKEN_LENGTH,
             has_error: false,
+            start_line: input.line,
+            start_col: input.col,
         }
     }
 
+    pub fn set_max_token_length(&mut self, max_token_length: usize) {
+        self.max_token_length = max_token_length;
+    }
+
     fn read_next(&mut self) -> Result<Token, InvalidTokenError> {
         // If the input char is whitespace, continue reading until it isn't
         self.read_while(&mut is_whitespace);
+        (self.start_line, self.start_col) = (self.input.line, self.input.col);
 
         // If input is EOF, return EOF token
         if self.input.eof() {
-            return Ok(Token::new(TokenType::Eof, &String::default(), self.input.line, self.input.col));
+            return Ok(self.token(TokenType::Eof, ""));
         }
 
         // Peek at the next character in the input stream to figure out what we need to do
@@ -142,42 +184,90 @@ impl TokenStream {
         }
 
         if ch == '"' {
-            let string_token = self.read_string();
-            if string_token.is_none() {
-                self.has_error = true;
-                return Err(InvalidTokenError {
-                    message: format!("Invalid string termination at {}:{}", self.input.line, self.input.col),
-                    line_as_string: self.input.get_current_line().to_string(),
-                    line: self.input.line,
-                    col: self.input.col,
-                });
-            } else { return Ok(string_token.unwrap()); }
+            return match self.read_string() {
+                Some(string_token) => Ok(string_token),
+                None => {
+                    self.has_error = true;
+                    Err(InvalidTokenError {
+                        code: ErrorCode::UnterminatedString,
+                        message: format!("Invalid string termination at {}:{}", self.input.line, self.input.col),
+                        line_as_string: self.input.get_current_line().to_string(),
+                        line: self.input.line,
+                        col: self.input.col,
+                    })
+                }
+            };
         }
 
-        if ch.is_digit(10) {
-            return Ok(self.read_number());
+        if ch == 'b' && self.input.peek_next() == Some('"') {
+            self.input.next();
+            return match self.read_bytes() {
+                Some(bytes_token) => Ok(bytes_token),
+                None => {
+                    self.has_error = true;
+                    Err(InvalidTokenError {
+                        code: ErrorCode::UnterminatedString,
+                        message: format!("Invalid byte string termination at {}:{}", self.input.line, self.input.col),
+                        line_as_string: self.input.get_current_line().to_string(),
+                        line: self.input.line,
+                        col: self.input.col,
+                    })
+                }
+            };
+        }
+
+        if ch.is_ascii_digit() {
+            return match self.read_number() {
+                Some(number_token) => Ok(number_token),
+                None => {
+                    self.has_error = true;
+                    Err(InvalidTokenError {
+                        code: ErrorCode::TokenTooLong,
+                        message: format!("Number literal too long at {}:{}", self.start_line, self.start_col),
+                        line_as_string: self.input.get_current_line().to_string(),
+                        line: self.start_line,
+                        col: self.start_col,
+                    })
+                }
+            };
         }
 
         if is_id_start(ch) {
-            return Ok(self.read_ident());
+            return match self.read_ident() {
+                Some(identifier_token) => Ok(identifier_token),
+                None => {
+                    self.has_error = true;
+                    Err(InvalidTokenError {
+                        code: ErrorCode::TokenTooLong,
+                        message: format!("Identifier too long at {}:{}", self.start_line, self.start_col),
+                        line_as_string: self.input.get_current_line().to_string(),
+                        line: self.start_line,
+                        col: self.start_col,
+                    })
+                }
+            };
         }
 
         if is_punctuation(ch) {
-            let punctuation_token = self.read_punctuation();
-            if punctuation_token.is_none() {
-                self.has_error = true;
-                return Err(InvalidTokenError {
-                    message: format!("Invalid operator at {}:{}", self.input.line, self.input.col),
-                    line_as_string: self.input.get_current_line().to_string(),
-                    line: self.input.line,
-                    col: self.input.col,
-                });
-            } else {return Ok(punctuation_token.unwrap());}
+            return match self.read_punctuation() {
+                Some(punctuation_token) => Ok(punctuation_token),
+                None => {
+                    self.has_error = true;
+                    Err(InvalidTokenError {
+                        code: ErrorCode::InvalidOperator,
+                        message: format!("Invalid operator at {}:{}", self.start_line, self.start_col),
+                        line_as_string: self.input.get_current_line().to_string(),
+                        line: self.start_line,
+                        col: self.start_col,
+                    })
+                }
+            };
         }
 
         // Illegal character detected here, skip this one and return an error
 
         let error = Err(InvalidTokenError {
+            code: ErrorCode::InvalidCharacter,
             message: format!(
                 "Invalid character at {}:{}",
                 self.input.line, self.input.col
@@ -199,47 +289,125 @@ impl TokenStream {
         return_string
     }
 
+    // Same as read_while, but stops keeping the characters once there are more than max_token_length of them.
+    // The rest of the token is still consumed so lexing continues after it, and None is returned
+    fn read_while_limited(&mut self, predicate: &mut dyn FnMut(char) -> bool) -> Option<String> {
+        let mut return_string = String::new();
+        let mut length = 0;
+        while !self.input.eof() && predicate(self.input.peek()) {
+            let ch = self.input.next();
+            length += 1;
+            if length <= self.max_token_length {
+                return_string.push(ch);
+            }
+        }
+
+        if length > self.max_token_length {
+            return None;
+        }
+        Some(return_string)
+    }
+
     fn skip_comment(&mut self) {
         self.read_while(&mut |x| x != '\n');
     }
 
+    // A string with `${...}` in it is an Interpolation token, kept as it was written like a byte string so the
+    // parser can still find the expressions in it. Any other string has its escapes taken out right away
     fn read_string(&mut self) -> Option<Token> {
-        let return_string = self.read_escaped('"');
+        self.input.next();
+        let (raw, interpolated) = self.read_raw_string()?;
+        if interpolated {
+            return Some(self.token(TokenType::Interpolation, &raw));
+        }
 
-        // if the string is None return none otherwise return a token
-        if return_string.is_none() {
-            return None;
-        } else {
-            return Some(Token::new(TokenType::String, &return_string.unwrap(), self.input.line, self.input.col));
+        let text = split_interpolation(&raw).into_iter().map(|segment| match segment {
+            Segment::Text(text) => text,
+            Segment::Code { .. } => unreachable!("The string has no interpolation"),
+        });
+        Some(self.token(TokenType::String, &text.collect::<String>()))
+    }
+
+    // Everything up to the closing quote, which is consumed but not returned, and whether there was a `${` in it.
+    // The code in `${}` can have strings and braces of its own, so its end is the '}' that matches the '{'
+    fn read_raw_string(&mut self) -> Option<(String, bool)> {
+        let mut raw = String::new();
+        let mut interpolated = false;
+        loop {
+            // Reaching EOF before the closing quote means the string was never terminated
+            if self.input.eof() {
+                return None;
+            }
+
+            let ch = self.input.next();
+            match ch {
+                '"' => return Some((raw, interpolated)),
+                '\\' => {
+                    raw.push(ch);
+                    if self.input.eof() {
+                        return None;
+                    }
+                    raw.push(self.input.next());
+                }
+                '$' if !self.input.eof() && self.input.peek() == '{' => {
+                    raw.push(ch);
+                    raw.push(self.input.next());
+                    interpolated = true;
+                    self.read_raw_code(&mut raw)?;
+                }
+                _ => raw.push(ch),
+            }
         }
     }
 
-    fn read_escaped(&mut self, end: char) -> Option<String> {
+    // The code of a `${}` after its '{', up to and including the matching '}'
+    fn read_raw_code(&mut self, raw: &mut String) -> Option<()> {
+        let mut depth = 1;
+        while depth > 0 {
+            if self.input.eof() {
+                return None;
+            }
+
+            let ch = self.input.next();
+            raw.push(ch);
+            match ch {
+                '{' => depth += 1,
+                '}' => depth -= 1,
+                '"' => {
+                    let (string, _) = self.read_raw_string()?;
+                    raw.push_str(&string);
+                    raw.push('"');
+                }
+                _ => {}
+            }
+        }
+        Some(())
+    }
+
+    // The contents of b"..." are kept as they were written, escapes included, the parser turns them into bytes
+    fn read_bytes(&mut self) -> Option<Token> {
         let mut escaped = false;
         let mut return_string = String::new();
 
         self.input.next();
         loop {
+            if self.input.eof() {
+                return None;
+            }
+
             let ch = self.input.next();
-            if escaped {
-                return_string.push(ch);
-                escaped = false;
-            } else if ch == '\\' {
-                escaped = true;
-            } else if ch == end {
+            if !escaped && ch == '"' {
                 break;
-            } else if self.input.eof() {
-                return None
-            } else {
-                return_string.push(ch);
             }
+            escaped = !escaped && ch == '\\';
+            return_string.push(ch);
         }
-        Some(return_string)
+        Some(self.token(TokenType::Bytes, &return_string))
     }
 
-    fn read_number(&mut self) -> Token {
+    fn read_number(&mut self) -> Option<Token> {
         let mut has_dec_point = false;
-        let number = self.read_while(&mut |ch: char| {
+        let number = self.read_while_limited(&mut |ch: char| {
             if ch == '.' {
                 if has_dec_point {
                     return false;
@@ -247,31 +415,27 @@ impl TokenStream {
                 has_dec_point = true;
                 return true;
             }
-            return ch.is_digit(10);
-        });
+            ch.is_ascii_digit()
+        })?;
 
         // If it is an integer, return an integer token
         if !has_dec_point {
-            return Token::new(TokenType::Integer, &number, self.input.line, self.input.col)
+            return Some(self.token(TokenType::Integer, &number))
         }
 
         // Otherwise return a float token
-        Token::new(TokenType::Numeral, &number, self.input.line, self.input.col)
+        Some(self.token(TokenType::Numeral, &number))
     }
 
-    fn read_ident(&mut self) -> Token {
-        let identifier = self.read_while(&mut is_id);
+    fn read_ident(&mut self) -> Option<Token> {
+        let identifier = self.read_while_limited(&mut is_id)?;
 
-        Token::new(
-            if is_keyword(&identifier) {
-                TokenType::Keyword
-            } else {
-                TokenType::Identifier
-            },
-            &identifier,
-            self.input.line,
-            self.input.col
-        )
+        let token_type = if is_keyword(&identifier) {
+            TokenType::Keyword(identifier.clone())
+        } else {
+            TokenType::Identifier
+        };
+        Some(self.token(token_type, &identifier))
     }
 
     fn read_punctuation(&mut self) -> Option<Token> {
@@ -286,15 +450,31 @@ impl TokenStream {
             ')' => TokenType::RightParen,
             '{' => TokenType::LeftBrace,
             '}' => TokenType::RightBrace,
+            '[' => TokenType::LeftBracket,
+            ']' => TokenType::RightBracket,
             '.' => TokenType::Dot,
+            '?' => TokenType::Question,
+            ':' => TokenType::Colon,
             _ => TokenType::Eof,
         };
 
         if single_token_type != TokenType::Eof {
-            return Some(Token::new(single_token_type, &self.input.next().to_string(), self.input.line, self.input.col));
+            let punctuation = self.input.next().to_string();
+            return Some(self.token(single_token_type, &punctuation));
+        }
+
+        // Operators are at most two characters long, so only the next character decides if the operator goes on.
+        // If the two don't make an operator together, the second one is left for the next token, so `-(` or `+;`
+        // are read as two tokens instead of one invalid operator
+        let mut punctuation = self.input.next().to_string();
+        if !self.input.eof() {
+            let pair = format!("{}{}", punctuation, self.input.peek());
+            if matches!(pair.as_str(), "==" | "!=" | ">=" | "<=" | "<<" | ">>") {
+                self.input.next();
+                punctuation = pair;
+            }
         }
 
-        let punctuation = self.read_while(&mut is_punctuation);
         let token_type = match punctuation.as_str() {
             "=" => TokenType::Equal,
             "==" => TokenType::EqualEqual,
@@ -303,18 +483,21 @@ impl TokenStream {
             ">=" => TokenType::GreaterEqual,
             "<" => TokenType::Less,
             "<=" => TokenType::LessEqual,
+            "<<" => TokenType::LessLess,
+            ">>" => TokenType::GreaterGreater,
             "!" => TokenType::Bang,
             "-" => TokenType::Minus,
             "+" => TokenType::Plus,
             "/" => TokenType::Slash,
             "*" => TokenType::Star,
+            "%" => TokenType::Percent,
             _ => return None
         };
-        Some(Token::new(
-            token_type,
-            &punctuation,
-            self.input.line,
-            self.input.col))
+        Some(self.token(token_type, &punctuation))
+    }
+
+    fn token(&self, token_type: TokenType, value: &str) -> Token {
+        Token::new(token_type, value, self.start_line, self.start_col)
     }
 
     pub fn peek(&self) -> Option<Token> {
@@ -325,27 +508,58 @@ impl TokenStream {
         }
     }
 
-    pub fn next(&mut self) -> Result<Token, InvalidTokenError> {
+    // Look n tokens ahead without consuming anything, peek_n(0) is the token the next call to next_token() returns.
+    // Errors found while lexing ahead are kept in the buffer and returned again when next_token() reaches them
+    pub fn peek_n(&mut self, n: usize) -> Result<&Token, InvalidTokenError> {
+        while self.lookahead.len() <= n {
+            let token = self.read_next();
+            self.lookahead.push_back(token);
+        }
+
+        match &self.lookahead[n] {
+            Ok(token) => Ok(token),
+            Err(e) => Err(e.clone()),
+        }
+    }
+
+    pub fn next_token(&mut self) -> Result<Token, InvalidTokenError> {
         if !self.has_started {
             self.has_started = true;
         }
-        self.current = match self.read_next() {
-            Ok(value) => value,
-            Err(e) => return Err(e),
+
+        // Drain whatever peek_n already lexed before reading new input
+        self.current = match self.lookahead.pop_front() {
+            Some(token) => token?,
+            None => self.read_next()?,
         };
 
         Result::Ok(self.current.clone())
     }
 
     pub fn eof(&mut self) -> bool {
-        !self.peek().is_none() && self.peek().unwrap().token_type == TokenType::Eof
+        self.peek().is_some_and(|token| token.token_type == TokenType::Eof)
+    }
+}
+
+// Yields every token (or lexing error) up to and including the EOF token, then stops
+impl Iterator for TokenStream {
+    type Item = Result<Token, InvalidTokenError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.eof() {
+            return None;
+        }
+        Some(self.next_token())
     }
 }
 
-fn is_keyword(word: &String) -> bool {
-    KEYWORDS.iter().any(|&i| i == word)
+fn is_keyword(word: &str) -> bool {
+    KEYWORD_SET.contains(word)
 }
 
+// Identifiers are ASCII letters, digits and a few symbols, plus λ. None of those can be written another way in
+// Unicode (λ has no decomposed form), so the same name can't be spelled with different characters and identifiers
+// don't need normalizing. Allowing other letters would change that
 fn is_id_start(ch: char) -> bool {
     Regex::new(r"[a-zA-Zλ_]")
         .unwrap()
@@ -353,7 +567,7 @@ fn is_id_start(ch: char) -> bool {
 }
 
 fn is_punctuation(ch: char) -> bool {
-    PUNCTS.iter().any(|&i| i == ch)
+    PUNCTS.contains(&ch)
 }
 
 fn is_whitespace(ch: char) -> bool {
@@ -363,3 +577,173 @@ fn is_whitespace(ch: char) -> bool {
 fn is_id(ch: char) -> bool {
     is_id_start(ch) || "-!?0123456789".contains(ch)
 }
+
+// Part of a string as it was written, either text with its escapes taken out or the code of a `${}`. The offset
+// is where the code starts in the string, in characters
+#[derive(Debug)]
+pub enum Segment {
+    Text(String),
+    Code { source: String, offset: usize },
+}
+
+// Splits a string the lexer read into its text and code. A backslash keeps the next character as it is, so `\${`
+// is just text. Inside `${}` nothing is taken out, the code is lexed again as it was written: strings in it keep
+// their escapes and can have `${}` of their own
+pub fn split_interpolation(raw: &str) -> Vec<Segment> {
+    let chars: Vec<char> = raw.chars().collect();
+    let mut segments = Vec::new();
+    let mut text = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' if i + 1 < chars.len() => {
+                text.push(chars[i + 1]);
+                i += 2;
+            }
+            '$' if chars.get(i + 1) == Some(&'{') => {
+                if !text.is_empty() {
+                    segments.push(Segment::Text(std::mem::take(&mut text)));
+                }
+                let end = code_end(&chars, i + 2);
+                let source: String = chars[i + 2..end].iter().collect();
+                segments.push(Segment::Code { source, offset: i + 2 });
+                i = end + 1;
+            }
+            ch => {
+                text.push(ch);
+                i += 1;
+            }
+        }
+    }
+    if !text.is_empty() || segments.is_empty() {
+        segments.push(Segment::Text(text));
+    }
+    segments
+}
+
+// Index of the '}' that closes the code starting at `i`, found the same way read_raw_code finds it
+fn code_end(chars: &[char], mut i: usize) -> usize {
+    let mut depth = 1;
+    while i < chars.len() {
+        match chars[i] {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return i;
+                }
+            }
+            '"' => i = string_end(chars, i + 1),
+            _ => {}
+        }
+        i += 1;
+    }
+    chars.len()
+}
+
+// Index of the quote that closes a string in the code of a `${}`, `i` is just after its opening quote
+fn string_end(chars: &[char], mut i: usize) -> usize {
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 2,
+            '"' => return i,
+            '$' if chars.get(i + 1) == Some(&'{') => i = code_end(chars, i + 2) + 1,
+            _ => i += 1,
+        }
+    }
+    chars.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lex(source: &str) -> Vec<Result<Token, InvalidTokenError>> {
+        let mut input_stream = InputStream::new(source);
+        TokenStream::new(&mut input_stream).collect()
+    }
+
+    #[test]
+    fn an_error_on_the_first_line_is_on_line_1() {
+        let tokens = lex("var a = 1 @ 2;");
+        let error = tokens.iter().find_map(|token| token.as_ref().err()).unwrap();
+        assert_eq!((error.line, error.col), (1, 11));
+        assert_eq!(error.line_as_string, "var a = 1 @ 2;");
+
+        let first = tokens[0].as_ref().unwrap();
+        assert_eq!((first.line, first.col), (1, 1));
+    }
+
+    #[test]
+    fn an_unterminated_string_is_an_error_not_a_panic() {
+        for source in ["\"abc", "\"abc\\", "\"", "b\"abc"] {
+            let tokens = lex(source);
+            let error = tokens.iter().find_map(|token| token.as_ref().err());
+            assert!(error.is_some(), "{:?} lexed without an error", source);
+        }
+    }
+
+    #[test]
+    fn peeking_ahead_doesnt_skip_tokens() {
+        let mut input_stream = InputStream::new("var a = 1;");
+        let mut tokens = TokenStream::new(&mut input_stream);
+        assert_eq!(tokens.peek_n(2).unwrap().value, "=");
+        assert_eq!(tokens.peek_n(0).unwrap().value, "var");
+        assert_eq!(tokens.peek_n(5).unwrap().token_type, TokenType::Eof);
+
+        let values: Vec<String> = tokens.map(|token| token.unwrap().value).collect();
+        assert_eq!(values, ["var", "a", "=", "1", ";", ""]);
+    }
+
+    #[test]
+    fn an_error_found_while_peeking_is_returned_by_next() {
+        let mut input_stream = InputStream::new("a @ b");
+        let mut tokens = TokenStream::new(&mut input_stream);
+        assert!(tokens.peek_n(1).is_err());
+        // Peeking past the error doesn't lose it
+        assert_eq!(tokens.peek_n(2).unwrap().value, "b");
+
+        assert_eq!(tokens.next_token().unwrap().value, "a");
+        let error = tokens.next_token().unwrap_err();
+        assert_eq!((error.line, error.col), (1, 3));
+        assert_eq!(tokens.next_token().unwrap().value, "b");
+    }
+
+    #[test]
+    fn keywords_resolve_in_any_order() {
+        let mut scrambled = KEYWORDS.to_vec();
+        scrambled.reverse();
+        scrambled.rotate_left(KEYWORDS.len() / 3);
+        scrambled.swap(0, 1);
+        assert_ne!(scrambled, KEYWORDS);
+
+        let scrambled_set: HashSet<&str> = scrambled.iter().copied().collect();
+        assert_eq!(scrambled_set, *KEYWORD_SET);
+        for keyword in scrambled {
+            assert!(is_keyword(keyword), "{} isn't a keyword", keyword);
+            let token = lex(keyword).remove(0).unwrap();
+            assert!(token.token_type.is_keyword(keyword), "{} lexed as {}", keyword, token.token_type);
+        }
+        assert!(!is_keyword("lambdas"));
+    }
+
+    #[test]
+    fn an_over_long_identifier_or_number_is_an_error() {
+        let mut input_stream = InputStream::new("var abcdefghij = 1;\nprint 12345678901;");
+        let mut tokens = TokenStream::new(&mut input_stream);
+        tokens.set_max_token_length(10);
+        let results: Vec<Result<Token, InvalidTokenError>> = tokens.collect();
+        let errors: Vec<&InvalidTokenError> = results.iter().filter_map(|token| token.as_ref().err()).collect();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].code, ErrorCode::TokenTooLong);
+        assert_eq!((errors[0].line, errors[0].col), (2, 7));
+        // Lexing goes on after the long token
+        assert_eq!(results.last().unwrap().as_ref().unwrap().token_type, TokenType::Eof);
+
+        let identifier = "a".repeat(DEFAULT_MAX_TOKEN_LENGTH + 1);
+        let error = lex(&identifier).into_iter().find_map(Result::err).unwrap();
+        assert_eq!(error.code, ErrorCode::TokenTooLong);
+        assert_eq!(error.message, "Identifier too long at 1:1");
+        assert!(lex(&identifier[1..]).iter().all(Result::is_ok));
+    }
+}
@@ -0,0 +1,84 @@
+#[macro_use]
+extern crate num_derive;
+extern crate num_traits as num_derived_traits;
+
+pub mod input_stream;
+pub mod lexer;
+pub mod errors;
+pub mod expressions;
+pub mod statements;
+pub mod parser;
+pub mod ast_printer;
+pub mod ast_json;
+pub mod ast_diff;
+pub mod unparser;
+pub mod lints;
+pub mod walk;
+pub mod environment;
+pub mod functions;
+pub mod interpreter;
+pub mod compiler;
+pub mod vm;
+
+use errors::WolffError;
+use expressions::LiteralValue;
+use input_stream::InputStream;
+use interpreter::AstInterpreter;
+use lexer::{Token, TokenStream};
+use parser::Parser;
+
+/// Runs Wolff code from another program. Everything a call defines is still there for the next one, the same as
+/// lines typed at the prompt. Nothing is printed besides what the code itself prints, a failure is only returned.
+///
+/// ```
+/// use wolff::expressions::LiteralValue;
+/// use wolff::Interpreter;
+///
+/// let mut interpreter = Interpreter::new();
+/// interpreter.run_str("var answer = 6 * 7;").unwrap();
+/// assert_eq!(interpreter.eval_expr("answer + 1").unwrap(), LiteralValue::Number(43.0));
+/// assert!(interpreter.run_str("print undefined_name;").is_err());
+/// ```
+pub struct Interpreter {
+    interpreter: AstInterpreter,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        Interpreter { interpreter: AstInterpreter::new() }
+    }
+
+    // The tree-walker underneath, e.g. to define natives or send the output somewhere else
+    pub fn ast_interpreter(&mut self) -> &mut AstInterpreter {
+        &mut self.interpreter
+    }
+
+    // Only the first lexing or parsing error is returned, the code doesn't run if there is any
+    pub fn run_str(&mut self, source: &str) -> Result<(), WolffError> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser::new(&tokens);
+        parser.set_source(source);
+        let statements = parser.parse_program().map_err(|mut errors| errors.swap_remove(0))?;
+        Ok(self.interpreter.interpret(&statements)?)
+    }
+
+    // A single expression, without a ';' after it
+    pub fn eval_expr(&mut self, source: &str) -> Result<LiteralValue, WolffError> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser::new(&tokens);
+        parser.set_source(source);
+        let expression = parser.parse_expression()?;
+        Ok(self.interpreter.interpret_expression(&expression)?)
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, WolffError> {
+    let mut input_stream = InputStream::new(source);
+    Ok(TokenStream::new(&mut input_stream).collect::<Result<_, _>>()?)
+}
@@ -0,0 +1,96 @@
+use crate::expressions::{Expr, LiteralValue};
+use crate::lexer::Token;
+use crate::statements::{Stmt, StmtVisitor};
+use crate::walk::{walk_stmt, Walk};
+
+// Looks for code that is valid but almost certainly not what was meant, the program still runs as usual
+pub struct Linter {
+    warnings: Vec<String>,
+}
+
+impl Default for Linter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Linter {
+    pub fn new() -> Self {
+        Linter { warnings: Vec::new() }
+    }
+
+    pub fn lint(mut self, statements: &[Stmt]) -> Vec<String> {
+        for statement in statements {
+            self.visit_stmt(statement);
+        }
+        self.warnings
+    }
+}
+
+// Only loops are looked at, everything else is just walked through
+impl Walk for Linter {
+    fn visit_stmt(&mut self, statement: &Stmt) {
+        // `while true` (or a for loop without a condition) can only be left with a break or a return
+        if let Stmt::While { keyword, condition, body, .. } = statement {
+            let always_true = matches!(condition, Expr::Literal { value: LiteralValue::Bool(true), .. });
+            if always_true && !body.accept(&mut ExitFinder { loop_depth: 0 }) {
+                self.warnings.push(format!(
+                    "{}:{} Loop never ends, its condition is always true and it has no break or return",
+                    keyword.line, keyword.col
+                ));
+            }
+        }
+        walk_stmt(self, statement);
+    }
+}
+
+// Tells if a loop body has a break or return that leaves the loop. A break inside a nested loop only
+// leaves that one, and a return inside a nested function returns from that function instead
+struct ExitFinder {
+    loop_depth: usize,
+}
+
+impl StmtVisitor<bool> for ExitFinder {
+    fn visit_block_stmt(&mut self, statements: &[Stmt]) -> bool {
+        statements.iter().any(|statement| statement.accept(self))
+    }
+
+    fn visit_break_stmt(&mut self, _keyword: &Token) -> bool {
+        self.loop_depth == 0
+    }
+
+    fn visit_continue_stmt(&mut self, _keyword: &Token) -> bool {
+        false
+    }
+
+    fn visit_expression_stmt(&mut self, _expression: &Expr) -> bool {
+        false
+    }
+
+    fn visit_function_stmt(&mut self, _name: &Token, _params: &[Token], _body: &[Stmt]) -> bool {
+        false
+    }
+
+    fn visit_if_stmt(&mut self, _keyword: &Token, _condition: &Expr, then_branch: &Stmt, else_branch: Option<&Stmt>) -> bool {
+        then_branch.accept(self) || else_branch.is_some_and(|else_branch| else_branch.accept(self))
+    }
+
+    fn visit_print_stmt(&mut self, _keyword: &Token, _expressions: &[Expr]) -> bool {
+        false
+    }
+
+    fn visit_return_stmt(&mut self, _keyword: &Token, _value: Option<&Expr>) -> bool {
+        true
+    }
+
+    fn visit_var_stmt(&mut self, _name: &Token, _initializer: &Expr) -> bool {
+        false
+    }
+
+    fn visit_while_stmt(&mut self, _keyword: &Token, _condition: &Expr, body: &Stmt, _increment: Option<&Expr>) -> bool {
+        self.loop_depth += 1;
+        let exits = body.accept(self);
+        self.loop_depth -= 1;
+        exits
+    }
+}
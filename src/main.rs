@@ -1,87 +1,310 @@
-#[macro_use]
-extern crate num_derive;
-extern crate num_traits as num_derived_traits;
-
-pub mod input_stream;
-pub mod lexer;
-pub mod errors;
-pub mod parser;
-pub mod vm;
-
-use input_stream::InputStream;
-use lexer::TokenStream;
-use vm::{VM, OpCode, Constant};
+#[cfg(feature = "measure-memory")]
+mod memory;
+
+use wolff::{ast_diff, errors, parser};
+use wolff::input_stream::InputStream;
+use wolff::lexer::{Token, TokenStream, TokenType};
+use wolff::parser::Parser;
+use wolff::ast_printer::AstPrinter;
+use wolff::ast_json::AstJson;
+use wolff::unparser::Unparser;
+use wolff::lints::Linter;
+use wolff::interpreter::AstInterpreter;
+use wolff::expressions::{Expr, LiteralValue};
+use wolff::statements::Stmt;
+use wolff::compiler::Compiler;
+use wolff::vm::{Chunk, Constant, VM};
+use std::cell::RefCell;
 use std::env;
 use std::fs;
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
 use rustyline::error::ReadlineError;
 use rustyline::{Editor, Result};
 
+// Where the REPL keeps its history when WOLFF_HISTORY doesn't say otherwise, under the XDG data directory
+const HISTORY_FILE: &str = "wolff/history.txt";
+
+const USAGE: &str = "\
+Usage: wolff [options] [file]
+
+Without a file the REPL starts, or the code piped into the standard input is run. With one the file is run.
+
+  --no-history                  Start the REPL without loading or saving its history
+  --repl-script <file>          Run a file, then start the REPL with what it defined
+  --calc \"<expression>\"         Compute an arithmetic expression on the VM
+  --tokens <file>               Show the tokens of a file
+  --ast <file>                  Show the AST of a file without running it
+  --ast-json <file>             Show the AST of a file as JSON
+  --ast-only-errors <file>      Show the AST of a file and run it, only showing the errors
+  --bytecode <file>             Run a file on the VM
+  --compile <file> <output>     Compile a file and save the chunk
+  --run-chunk <file>            Run a saved chunk on the VM
+  --dump-constants <file>       Show the constants of a compiled file
+  --check-determinism <file>    Run a file twice and compare what it printed
+  --ast-compare <file> <file>   Tell if two files parse to the same AST, and where they differ if not
+  --measure-memory <file>       Run a file and show its peak memory use
+  --grammar                     Show the grammar of the language
+  --debug                       Show every instruction the VM runs
+  --stacktrace                  Show the VM stack after every instruction";
+
+// Why running a file failed, the error itself was already reported. Each one has the exit code from the sysexits
+// convention that Lox uses
+#[derive(Clone, Copy, Debug)]
+enum Failure {
+    // The code didn't lex, parse or compile, or a compiled chunk is broken
+    InvalidCode,
+    Runtime,
+    // The file couldn't be read
+    NoInput,
+    // The command line was wrong, e.g. an option is missing its file
+    Usage,
+    // The output file couldn't be written
+    CannotCreate,
+    // Running the program twice gave two different outputs
+    Nondeterministic,
+}
+
+impl Failure {
+    fn exit_code(self) -> i32 {
+        match self {
+            Failure::InvalidCode => 65,
+            Failure::NoInput => 66,
+            Failure::Usage => 64,
+            Failure::Runtime => 70,
+            Failure::CannotCreate => 73,
+            // Like diff, 1 only says the two runs differ
+            Failure::Nondeterministic => 1,
+        }
+    }
+}
+
+fn exit_on_failure(result: std::result::Result<(), Failure>) {
+    if let Err(failure) = result {
+        std::process::exit(failure.exit_code());
+    }
+}
+
+fn exit_with_usage(usage: &str) -> ! {
+    println!("Usage: {}", usage);
+    std::process::exit(Failure::Usage.exit_code());
+}
+
+// How the VM runs a chunk, set with --debug and --stacktrace
+#[derive(Clone, Copy, Default)]
+struct VmOptions {
+    debug: bool,
+    stacktrace: bool,
+}
+
+#[cfg(feature = "measure-memory")]
+#[global_allocator]
+static ALLOCATOR: memory::TrackingAllocator = memory::TrackingAllocator;
+
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    print_splash_screen();
-
-    // Chunk testing area
-    let mut vm = VM::new(true, true);
-
-    let mut offset = vm.chunk.add_constant(Constant::Integer(45688874));
-    vm.chunk.write_chunk(OpCode::Constant as u8, 0);
-    vm.chunk.write_chunk(offset, 0);
-
-    offset = vm.chunk.add_constant(Constant::Float(1.2356));
-    vm.chunk.write_chunk(OpCode::Constant as u8, 1);
-    vm.chunk.write_chunk(offset, 1);
-    offset = vm.chunk.add_constant(Constant::Float(256.235444));
-    vm.chunk.write_chunk(OpCode::Constant as u8, 2);
-    vm.chunk.write_chunk(offset, 2);
-    offset = vm.chunk.add_constant(Constant::Float(4589845542425.2));
-    vm.chunk.write_chunk(OpCode::Constant as u8, 3);
-    vm.chunk.write_chunk(offset, 3);
-    offset = vm.chunk.add_constant(Constant::Integer(10));
-    vm.chunk.write_chunk(OpCode::Constant as u8, 3);
-    vm.chunk.write_chunk(offset, 3);
-    offset = vm.chunk.add_constant(Constant::Integer(-5));
-    vm.chunk.write_chunk(OpCode::Constant as u8, 3);
-    vm.chunk.write_chunk(offset, 3);
-    offset = vm.chunk.add_constant(Constant::Integer(800));
-    vm.chunk.write_chunk(OpCode::Constant as u8, 3);
-    vm.chunk.write_chunk(offset, 3);
-
-    vm.chunk.write_chunk(OpCode::Negate as u8, 4);
-    vm.chunk.write_chunk(OpCode::Negate as u8, 4);
-
-    vm.chunk.write_chunk(OpCode::Addition as u8, 4);
-    vm.chunk.write_chunk(OpCode::Subtraction as u8, 4);
-    
-    vm.chunk.write_chunk(OpCode::Return as u8, 4);
-
-    let result_code = vm.interpret();
-    println!("VM returned status code {}", result_code);
-
-    // End chunk testing area
-
-    return match args.get(1) {
-        Some(filename) => start_lexer_from_file(filename).expect("Something went wrong while reading the file"),
-        None => start_prompt().expect("Something went wrong"),
+    let mut args: Vec<String> = env::args().collect();
+    // These can go anywhere on the command line, they only change how the VM runs
+    let vm_options = VmOptions {
+        debug: take_flag(&mut args, "--debug"),
+        stacktrace: take_flag(&mut args, "--stacktrace"),
     };
+
+    match args.get(1).map(String::as_str) {
+        Some("--calc") => match args.get(2) {
+            Some(expression) => exit_on_failure(calculate(expression)),
+            None => exit_with_usage("wolff --calc \"<expression>\""),
+        },
+        Some("--ast-only-errors") => match args.get(2) {
+            Some(filename) => {
+                print_splash_screen();
+                exit_on_failure(print_ast_and_errors(filename))
+            }
+            None => exit_with_usage("wolff --ast-only-errors <file>"),
+        },
+        Some("--bytecode") => match args.get(2) {
+            Some(filename) => {
+                print_splash_screen();
+                exit_on_failure(run_bytecode(filename, vm_options))
+            }
+            None => exit_with_usage("wolff --bytecode <file>"),
+        },
+        Some("--compile") => match (args.get(2), args.get(3)) {
+            (Some(filename), Some(output)) => exit_on_failure(compile_file(filename, output)),
+            _ => exit_with_usage("wolff --compile <file> <output>"),
+        },
+        Some("--run-chunk") => match args.get(2) {
+            Some(filename) => {
+                print_splash_screen();
+                exit_on_failure(run_chunk_file(filename, vm_options))
+            }
+            None => exit_with_usage("wolff --run-chunk <file>"),
+        },
+        Some("--dump-constants") => match args.get(2) {
+            Some(filename) => exit_on_failure(dump_constants(filename)),
+            None => exit_with_usage("wolff --dump-constants <file>"),
+        },
+        Some("--check-determinism") => match args.get(2) {
+            Some(filename) => {
+                print_splash_screen();
+                exit_on_failure(check_determinism(filename))
+            }
+            None => exit_with_usage("wolff --check-determinism <file>"),
+        },
+        Some("--ast-compare") => match (args.get(2), args.get(3)) {
+            // Like diff, the exit code tells scripts whether the programs differ
+            (Some(first), Some(second)) => {
+                if !compare_asts(first, second) {
+                    std::process::exit(1);
+                }
+            }
+            _ => exit_with_usage("wolff --ast-compare <file> <file>"),
+        },
+        Some("--measure-memory") => match args.get(2) {
+            Some(filename) => {
+                print_splash_screen();
+                exit_on_failure(measure_memory(filename))
+            }
+            None => exit_with_usage("wolff --measure-memory <file>"),
+        },
+        Some("--grammar") => print!("{}", parser::GRAMMAR),
+        // Left out of the usage, it is only there so lexer performance can be measured and reported
+        Some("--lex-only-timing") => match (args.get(2), args.get(3).map(|runs| runs.parse::<usize>())) {
+            (Some(filename), None) => exit_on_failure(lex_timing(filename, 100)),
+            (Some(filename), Some(Ok(runs))) if runs > 0 => exit_on_failure(lex_timing(filename, runs)),
+            _ => exit_with_usage("wolff --lex-only-timing <file> [runs]"),
+        },
+        Some("--tokens") => match args.get(2) {
+            Some(filename) => exit_on_failure(with_file_contents(filename, print_tokens)),
+            None => exit_with_usage("wolff --tokens <file>"),
+        },
+        Some("--ast") => match args.get(2) {
+            Some(filename) => exit_on_failure(with_file_contents(filename, print_ast)),
+            None => exit_with_usage("wolff --ast <file>"),
+        },
+        Some("--ast-json") => match args.get(2) {
+            Some(filename) => exit_on_failure(with_file_contents(filename, print_ast_json)),
+            None => exit_with_usage("wolff --ast-json <file>"),
+        },
+        Some("--help") => println!("{}", USAGE),
+        Some("--repl-script") => match args.get(2) {
+            Some(filename) => {
+                print_splash_screen();
+                // Same as starting the prompt and loading the file with :load
+                let mut interpreter = AstInterpreter::new();
+                load_file(filename, &mut interpreter);
+                start_prompt(Some(&history_path()), interpreter).expect("Something went wrong")
+            }
+            None => exit_with_usage("wolff --repl-script <file>"),
+        },
+        Some("--no-history") => {
+            print_splash_screen();
+            start_prompt(None, AstInterpreter::new()).expect("Something went wrong")
+        }
+        Some(option) if option.starts_with("--") => {
+            println!("[\x1b[91mERR\x1b[0m] Unknown option '{}'\n\n{}", option, USAGE);
+            std::process::exit(Failure::Usage.exit_code());
+        }
+        Some(filename) => {
+            print_splash_screen();
+            exit_on_failure(interpret_file(filename));
+        }
+        // Piped in code is run like a file, without the splash screen so only what the program prints comes out
+        None if !io::stdin().is_terminal() => {
+            exit_on_failure(interpret_stdin());
+        }
+        None => {
+            print_splash_screen();
+            start_prompt(Some(&history_path()), AstInterpreter::new()).expect("Something went wrong")
+        }
+    }
+}
+
+// Removes every occurrence of the flag from the arguments, and tells if there was any
+fn take_flag(args: &mut Vec<String>, flag: &str) -> bool {
+    let count = args.len();
+    args.retain(|arg| arg != flag);
+    args.len() != count
+}
+
+fn with_file_contents(filename: &str, run: fn(&str)) -> std::result::Result<(), Failure> {
+    let contents = fs::read_to_string(filename).map_err(|e| {
+        println!("[\x1b[91mERR\x1b[0m] Can't read '{}': {}", filename, e);
+        Failure::NoInput
+    })?;
+    run(&contents);
+    Ok(())
 }
 
 fn print_splash_screen() {
     println!("\x1b[1mWolff interpreter {}\x1b[0m", env!("CARGO_PKG_VERSION"));
 }
 
-fn start_prompt() -> Result<()> {
+// WOLFF_HISTORY is used as it is, otherwise the history goes in $XDG_DATA_HOME, or ~/.local/share when that isn't
+// set, so the same history is found from any directory. Without a home it falls back to the current directory
+fn history_path() -> PathBuf {
+    // An empty variable counts as not set, like XDG says
+    let var = |name: &str| env::var_os(name).filter(|value| !value.is_empty()).map(PathBuf::from);
+    if let Some(path) = var("WOLFF_HISTORY") {
+        return path;
+    }
+
+    let data_home = var("XDG_DATA_HOME").or_else(|| var("HOME").map(|home| home.join(".local/share")));
+    match data_home {
+        Some(data_home) => data_home.join(HISTORY_FILE),
+        None => PathBuf::from("history.txt"),
+    }
+}
 
+// Without a history file nothing is loaded or saved, the history only lasts for the session. The whole session
+// shares the one interpreter so variables survive between lines, it can already have things defined before the
+// prompt starts
+fn start_prompt(history_file: Option<&Path>, mut interpreter: AstInterpreter) -> Result<()> {
     let mut rl = Editor::<()>::new()?;
-    if rl.load_history("history.txt").is_err() {
-        println!("No previous history.");
+    if let Some(history_file) = history_file {
+        if let Some(directory) = history_file.parent().filter(|directory| !directory.as_os_str().is_empty()) {
+            if let Err(e) = fs::create_dir_all(directory) {
+                println!("[\x1b[91mERR\x1b[0m] Can't create '{}': {}", directory.display(), e);
+            }
+        }
+        if rl.load_history(history_file).is_err() {
+            println!("No previous history.");
+        }
     }
+    // Lines of a statement that isn't finished yet, e.g. a function whose body is still open
+    let mut pending = String::new();
     loop {
-        let readline = rl.readline("\x1b[1mλ \x1b[0m");
+        let prompt = if pending.is_empty() { "\x1b[1mλ \x1b[0m" } else { "\x1b[1m... \x1b[0m" };
+        let readline = rl.readline(prompt);
         match readline {
             Ok(line) => {
                 rl.add_history_entry(line.as_str());
-                start_lexer(&line);
+                // Saved before the line runs, so the history survives even if running it brings the REPL down
+                if let Some(history_file) = history_file {
+                    if let Err(e) = rl.save_history(history_file) {
+                        println!("[\x1b[91mERR\x1b[0m] Can't save history to '{}': {}", history_file.display(), e);
+                    }
+                }
+                if pending.is_empty() && line.trim_start().starts_with(':') {
+                    if !run_command(&line, &mut interpreter) {
+                        break;
+                    }
+                    continue;
+                }
+
+                pending.push_str(&line);
+                // A lone expression doesn't need its ';' at the prompt, `1 + 2` shows 3 right away
+                if is_lone_expression(&pending) {
+                    pending.push(';');
+                }
+                pending.push('\n');
+                if !is_unfinished(&pending) {
+                    interpret_string_prompt(&std::mem::take(&mut pending), &mut interpreter);
+                }
             },
+            // Only throws away the unfinished statement, the REPL keeps going
+            Err(ReadlineError::Interrupted) if !pending.is_empty() => pending.clear(),
             Err(ReadlineError::Interrupted) => {
                 println!("Interruption detected. Halting.");
                 break
@@ -96,34 +319,560 @@ fn start_prompt() -> Result<()> {
             }
         }
     }
-    rl.save_history("history.txt")
+    Ok(())
+}
+
+const COMMANDS_HELP: &str = "\
+:help           Show this list
+:quit           Leave the prompt
+:load <file>    Run a file, whatever it defines can be used afterwards
+:save <file>    Write what the session defined to a file, :load brings it back
+:clear          Forget everything the session defined
+:tokens <code>  Show the tokens the code is made of
+:ast <code>     Show the AST of the code without running it";
+
+// REPL meta-commands start with ':' and aren't Wolff code, e.g. `:load lib.wl`. Returns false when the REPL
+// should stop
+fn run_command(line: &str, interpreter: &mut AstInterpreter) -> bool {
+    let line = line.trim();
+    let (command, argument) = match line.split_once(char::is_whitespace) {
+        Some((command, argument)) => (command, argument.trim()),
+        None => (line, ""),
+    };
+
+    match command {
+        ":load" if !argument.is_empty() => load_file(argument, interpreter),
+        ":load" => println!("Usage: :load <file>"),
+        ":save" if !argument.is_empty() => save_session(argument, interpreter),
+        ":save" => println!("Usage: :save <file>"),
+        ":clear" => interpreter.reset(),
+        ":tokens" if !argument.is_empty() => print_tokens(argument),
+        ":tokens" => println!("Usage: :tokens <code>"),
+        ":ast" if !argument.is_empty() => print_ast(argument),
+        ":ast" => println!("Usage: :ast <code>"),
+        ":help" => println!("{}", COMMANDS_HELP),
+        ":quit" => {
+            println!("Halting.");
+            return false;
+        }
+        _ => println!("[\x1b[91mERR\x1b[0m] Unknown command '{}', :help lists them", command),
+    }
+    true
+}
+
+// One token per line with where it starts (line:col), its type and its text
+fn print_tokens(code: &str) {
+    if let Some(tokens) = tokenize(code) {
+        for token in tokens {
+            println!("{}:{}\t{}\t{}", token.line, token.col, token.token_type, token.value);
+        }
+    }
+}
+
+// A lone expression doesn't need a ';', the same as at the prompt
+fn print_ast(code: &str) {
+    let mut printer = AstPrinter;
+    if is_lone_expression(code) {
+        if let Some(tokens) = tokenize(code) {
+            if let Ok(expression) = Parser::new(&tokens).parse_expression() {
+                println!("{}", printer.print_expr(&expression));
+            }
+        }
+    } else if let Some(statements) = parse(code) {
+        for statement in statements.iter() {
+            println!("{}", printer.print_stmt(statement));
+        }
+    }
+}
+
+// Parsed without the linter, its warnings would end up in the middle of the JSON
+fn print_ast_json(code: &str) {
+    let tokens = match tokenize(code) {
+        Some(tokens) => tokens,
+        None => return,
+    };
+    let mut parser = Parser::new(&tokens);
+    parser.set_source(code);
+    match parser.parse_program() {
+        Ok(statements) => println!("{}", AstJson.program(&statements)),
+        Err(errors) => errors.iter().for_each(print_parser_error),
+    }
+}
+
+// Run a file in an existing session, so whatever it defines can be used afterwards
+fn load_file(filename: &str, interpreter: &mut AstInterpreter) {
+    match fs::read_to_string(filename) {
+        // The REPL goes on either way, the error was already shown
+        Ok(contents) => {
+            let _ = interpret_string(&contents, interpreter);
+        }
+        Err(e) => println!("[\x1b[91mERR\x1b[0m] Can't read '{}': {}", filename, e),
+    }
+}
+
+// Write the global definitions of the session as Wolff source, so `:load` can bring them back later
+fn save_session(filename: &str, interpreter: &AstInterpreter) {
+    let globals = interpreter.globals();
+    let globals = globals.borrow();
+
+    // Functions go first and everything is sorted by name, so saving the same session twice gives the same file
+    // Natives are defined by the interpreter itself, so they are already there when the file is loaded
+    let mut definitions: Vec<_> = globals
+        .values()
+        .iter()
+        .filter(|(_, value)| !matches!(value, LiteralValue::NativeFunction(_)))
+        .collect();
+    definitions.sort_by_key(|(name, value)| (!matches!(value, LiteralValue::Function(_)), name.as_str()));
+
+    let mut unparser = Unparser::new();
+    let mut source = String::new();
+    for (name, value) in definitions {
+        source.push_str(&unparser.unparse_definition(name, value));
+        source.push('\n');
+    }
+
+    if let Err(e) = fs::write(filename, source) {
+        println!("[\x1b[91mERR\x1b[0m] Can't write '{}': {}", filename, e);
+    }
+}
+
+fn interpret_file(filename: &str) -> std::result::Result<(), Failure> {
+    let contents = fs::read_to_string(filename).map_err(|e| {
+        println!("[\x1b[91mERR\x1b[0m] Can't read '{}': {}", filename, e);
+        Failure::NoInput
+    })?;
+
+    let mut interpreter = AstInterpreter::new();
+    interpret_string(&contents, &mut interpreter)
+}
+
+fn interpret_stdin() -> std::result::Result<(), Failure> {
+    let mut contents = String::new();
+    io::stdin().read_to_string(&mut contents).map_err(|e| {
+        println!("[\x1b[91mERR\x1b[0m] Can't read the standard input: {}", e);
+        Failure::NoInput
+    })?;
+
+    let mut interpreter = AstInterpreter::new();
+    interpret_string(&contents, &mut interpreter)
+}
+
+// Print the AST of the file and run it with everything it prints thrown away, so only the errors are left. It exits
+// like running the file would
+fn print_ast_and_errors(filename: &str) -> std::result::Result<(), Failure> {
+    let contents = fs::read_to_string(filename).map_err(|e| {
+        println!("[\x1b[91mERR\x1b[0m] Can't read '{}': {}", filename, e);
+        Failure::NoInput
+    })?;
+
+    let statements = parse(&contents).ok_or(Failure::InvalidCode)?;
+    let mut printer = AstPrinter;
+    for statement in statements.iter() {
+        println!("{}", printer.print_stmt(statement));
+    }
+
+    let mut interpreter = AstInterpreter::with_output(Box::new(io::sink()));
+    interpreter.interpret(&statements).map_err(|e| {
+        print_runtime_error(&e);
+        Failure::Runtime
+    })
+}
+
+// Everything a program prints, kept in memory instead of written out. Clones share the same buffer, so the
+// output can still be read after the interpreter was given its own copy
+#[derive(Clone, Default)]
+struct CapturedOutput(Rc<RefCell<Vec<u8>>>);
+
+impl Write for CapturedOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Tells if the two files parse to the same program, a file that can't be read or parsed counts as different
+fn compare_asts(first: &str, second: &str) -> bool {
+    let parse_file = |filename: &str| match fs::read_to_string(filename) {
+        Ok(contents) => parse(&contents),
+        Err(e) => {
+            println!("[\x1b[91mERR\x1b[0m] Can't read '{}': {}", filename, e);
+            None
+        }
+    };
+    let (first, second) = match (parse_file(first), parse_file(second)) {
+        (Some(first), Some(second)) => (first, second),
+        _ => return false,
+    };
+
+    match ast_diff::compare_programs(&first, &second) {
+        Ok(()) => {
+            println!("Both files have the same AST");
+            true
+        }
+        Err(difference) => {
+            println!("[\x1b[91mERR\x1b[0m] The ASTs differ at {}", difference);
+            false
+        }
+    }
+}
+
+// What a run printed, and the runtime error it ended with if there was one
+type Run = (String, Option<String>);
+
+// Run the statements twice, each time in a fresh interpreter
+fn run_twice(statements: &[Stmt]) -> (Run, Run) {
+    let run = || {
+        let output = CapturedOutput::default();
+        let mut interpreter = AstInterpreter::with_output(Box::new(output.clone()));
+        // A runtime error is part of the result too, it has to happen the same way both times
+        let error = interpreter.interpret(statements).err().map(|e| e.to_string());
+        let printed = String::from_utf8_lossy(&output.0.borrow()).into_owned();
+        (printed, error)
+    };
+    (run(), run())
+}
+
+// Run the file twice and compare what the two runs printed. Anything that changes between them (e.g. the
+// iteration order of a HashMap leaking into the output) is reported, and the exit code tells scripts about it
+fn check_determinism(filename: &str) -> std::result::Result<(), Failure> {
+    let contents = fs::read_to_string(filename).map_err(|e| {
+        println!("[\x1b[91mERR\x1b[0m] Can't read '{}': {}", filename, e);
+        Failure::NoInput
+    })?;
+    let statements = parse(&contents).ok_or(Failure::InvalidCode)?;
+
+    let (first, second) = run_twice(&statements);
+    if first == second {
+        println!("Both runs gave the same output");
+        return Ok(());
+    }
+
+    println!("[\x1b[91mERR\x1b[0m] The two runs gave different output");
+    let first_lines: Vec<&str> = first.0.lines().collect();
+    let second_lines: Vec<&str> = second.0.lines().collect();
+    match (0..first_lines.len().max(second_lines.len())).find(|&i| first_lines.get(i) != second_lines.get(i)) {
+        Some(i) => {
+            println!("  first difference on output line {}:", i + 1);
+            println!("  \x1b[96m|\x1b[0m {}", first_lines.get(i).unwrap_or(&"<no line>"));
+            println!("  \x1b[96m|\x1b[0m {}", second_lines.get(i).unwrap_or(&"<no line>"));
+        }
+        None if first.0 != second.0 => println!("  the output differs only in whitespace at the end"),
+        None => println!("  the runs ended with different errors: {:?} and {:?}", first.1, second.1),
+    }
+    Err(Failure::Nondeterministic)
 }
 
-fn start_lexer_from_file(filename: &String) -> Result<()> {
-    let contents = fs::read_to_string(filename.as_str()).expect("Error when opening file");
+// Run the file and report the most memory that was allocated at any one time while it ran
+#[cfg(feature = "measure-memory")]
+fn measure_memory(filename: &str) -> std::result::Result<(), Failure> {
+    let contents = fs::read_to_string(filename).map_err(|e| {
+        println!("[\x1b[91mERR\x1b[0m] Can't read '{}': {}", filename, e);
+        Failure::NoInput
+    })?;
+
+    memory::reset_peak();
+    let mut interpreter = AstInterpreter::new();
+    let _ = interpret_string(&contents, &mut interpreter);
+    println!("Peak memory: {} bytes", memory::peak());
+    Ok(())
+}
 
-    let return_value = start_lexer(&contents);
-    return Result::Ok(return_value)
+#[cfg(not(feature = "measure-memory"))]
+fn measure_memory(_filename: &str) -> std::result::Result<(), Failure> {
+    println!("[\x1b[91mERR\x1b[0m] Memory can't be measured, build with `cargo build --features measure-memory` first");
+    Err(Failure::Usage)
+}
+
+// Lexes the file again and again without parsing it, the time of a run includes setting up its input stream
+fn lex_timing(filename: &str, runs: usize) -> std::result::Result<(), Failure> {
+    let contents = fs::read_to_string(filename).map_err(|e| {
+        println!("[\x1b[91mERR\x1b[0m] Can't read '{}': {}", filename, e);
+        Failure::NoInput
+    })?;
+    // Errors are reported once here, the timed runs would only stop at them
+    let token_count = tokenize(&contents).ok_or(Failure::InvalidCode)?.len();
+
+    let mut timings: Vec<Duration> = (0..runs)
+        .map(|_| {
+            let start = Instant::now();
+            let mut input_stream = InputStream::new(&contents);
+            let count = TokenStream::new(&mut input_stream).count();
+            let elapsed = start.elapsed();
+            // Keeps the lexing from being optimized away
+            assert_eq!(count, token_count);
+            elapsed
+        })
+        .collect();
+    timings.sort();
+
+    println!("Tokens: {}", token_count);
+    println!("Runs: {}", runs);
+    println!("Min: {:?}", timings[0]);
+    println!("Median: {:?}", timings[runs / 2]);
+    println!("Max: {:?}", timings[runs - 1]);
+    Ok(())
 }
 
 fn print_error_message(error: &errors::InvalidTokenError) {
-    println!("[\x1b[91mERR\x1b[0m] {}", error.message);
-    println!("  \x1b[96m|\x1b[0m {}", error.line_as_string);
-    println!("  \x1b[96m|\x1b[0m \x1b[93m{:>width$}\x1b[0m", "^", width = (error.col+1) as usize);
+    println!("[\x1b[91mERR\x1b[0m] [{}] {}", error.code, error.message);
+    print_source_context(&error.line_as_string, error.col);
+}
+
+// Syntax errors at the REPL get the line that was typed under them too, it is the source the parser was given.
+// Runtime errors don't, a function defined by an earlier line fails at a position in that line instead
+fn print_parser_error(error: &errors::ParserError) {
+    println!("[\x1b[91mERR\x1b[0m] [{}] {}:{} {}", error.code, error.line, error.col, error.message);
+    if !error.line_as_string.is_empty() {
+        print_source_context(&error.line_as_string, error.col);
+    }
+}
+
+// The line an error is on, with a caret under the column
+fn print_source_context(line: &str, col: usize) {
+    println!("  \x1b[96m|\x1b[0m {}", line);
+    println!("  \x1b[96m|\x1b[0m \x1b[93m{:>width$}\x1b[0m", "^", width = col);
+}
+
+fn print_compiler_error(error: &errors::CompilerError) {
+    println!("[\x1b[91mERR\x1b[0m] {}", error);
 }
 
-fn start_lexer(contents: &String) {
-    let mut input_stream = InputStream::new(&contents);
+fn print_runtime_error(error: &errors::InterpreterRuntimeError) {
+    println!("[\x1b[91mERR\x1b[0m] {}", error);
+}
+
+fn print_vm_error(error: &errors::VMRuntimeError) {
+    println!("[\x1b[91mERR\x1b[0m] {}", error);
+}
+
+// Lex the whole input, returns None if there were any invalid tokens (after reporting all of them)
+fn tokenize(contents: &str) -> Option<Vec<Token>> {
+    let mut input_stream = InputStream::new(contents);
     let mut lexer = TokenStream::new(&mut input_stream);
+    let mut tokens = Vec::new();
+
+    for token in lexer.by_ref() {
+        match token {
+            Ok(new_token) => tokens.push(new_token),
+            Err(e) => print_error_message(&e),
+        };
+    }
+
+    if lexer.has_error {
+        return None;
+    }
+    Some(tokens)
+}
+
+// Input is unfinished if it only fails to parse because it ended too early, e.g. a '{' that isn't closed yet or a
+// statement without its ';'. Anything that fails before the end is a real error, and so is anything the lexer
+// rejects, those are reported once the input runs
+fn is_unfinished(contents: &str) -> bool {
+    let tokens = match tokenize_quietly(contents) {
+        Some(tokens) => tokens,
+        None => return false,
+    };
+    let eof = match tokens.last() {
+        Some(eof) => eof,
+        None => return false,
+    };
+
+    match Parser::new(&tokens).parse_program() {
+        Ok(_) => false,
+        Err(errors) => errors.last().is_some_and(|error| error.line == eof.line && error.col == eof.col),
+    }
+}
+
+// A statement starting with '{' is a block, the same as in a file, so `{}` is an empty block and not a map
+fn is_lone_expression(contents: &str) -> bool {
+    if contents.trim_start().starts_with('{') {
+        return false;
+    }
+    tokenize_quietly(contents).is_some_and(|tokens| Parser::new(&tokens).parse_expression().is_ok())
+}
+
+// Same as tokenize without reporting anything, for looking at input before deciding what to do with it
+fn tokenize_quietly(contents: &str) -> Option<Vec<Token>> {
+    let mut input_stream = InputStream::new(contents);
+    TokenStream::new(&mut input_stream).collect::<std::result::Result<_, _>>().ok()
+}
+
+// Lex and parse the input, returns None if there were any errors (after reporting all of them)
+fn parse(contents: &str) -> Option<Vec<Stmt>> {
+    let tokens = tokenize(contents)?;
+    let mut parser = Parser::new(&tokens);
+    parser.set_source(contents);
+    let statements = match parser.parse_program() {
+        Ok(statements) => statements,
+        Err(errors) => {
+            for error in &errors {
+                print_parser_error(error);
+            }
+            return None;
+        }
+    };
+    for warning in Linter::new().lint(&statements) {
+        println!("[\x1b[93mWARN\x1b[0m] {}", warning);
+    }
+    Some(statements)
+}
+
+fn interpret_string(contents: &str, interpreter: &mut AstInterpreter) -> std::result::Result<(), Failure> {
+    let statements = parse(contents).ok_or(Failure::InvalidCode)?;
+
+    interpreter.interpret(&statements).map_err(|e| {
+        print_runtime_error(&e);
+        Failure::Runtime
+    })
+}
+
+// Same as interpret_string, but errors are only reported, the prompt keeps going after them
+fn interpret_string_prompt(line: &str, interpreter: &mut AstInterpreter) {
+    let statements = match parse(line) {
+        Some(statements) => statements,
+        None => return,
+    };
 
-    while !lexer.eof() {
-        match lexer.next() {
-            Ok(new_token) => println!("{}: {}", new_token.token_type, new_token.value),
-            Err(e) => {
-                print_error_message(&e);
+    // Like a calculator, the value of an expression typed at the prompt is shown. Assignments are left alone,
+    // they would only repeat the value that was just typed
+    let statements: Vec<Stmt> = statements
+        .into_iter()
+        .map(|statement| match statement {
+            Stmt::Expression { expression } if !matches!(expression, Expr::Assign { .. } | Expr::IndexAssign { .. }) => {
+                // There was no print typed, the keyword is made up at the start of the line
+                let keyword = Token::new(TokenType::Keyword("print".to_string()), "print", 1, 0);
+                Stmt::Print { keyword, expressions: vec![expression] }
             }
+            statement => statement,
+        })
+        .collect();
+
+    if let Err(e) = interpreter.interpret(&statements) {
+        print_runtime_error(&e);
+    }
+}
+
+// Read, parse and compile a file, what went wrong was already reported when it fails
+fn compile_file_to_chunk(filename: &str) -> std::result::Result<Chunk, Failure> {
+    let contents = fs::read_to_string(filename).map_err(|e| {
+        println!("[\x1b[91mERR\x1b[0m] Can't read '{}': {}", filename, e);
+        Failure::NoInput
+    })?;
+    let statements = parse(&contents).ok_or(Failure::InvalidCode)?;
+    Compiler::new().compile(&statements).map_err(|e| {
+        print_compiler_error(&e);
+        Failure::InvalidCode
+    })
+}
+
+// Run a file on the VM instead of the tree-walking interpreter, only what the compiler supports can run this way
+fn run_bytecode(filename: &str, options: VmOptions) -> std::result::Result<(), Failure> {
+    let chunk = compile_file_to_chunk(filename)?;
+    run_chunk(&chunk, options)
+}
+
+fn run_chunk(chunk: &Chunk, options: VmOptions) -> std::result::Result<(), Failure> {
+    VM::from_chunk(chunk, options.debug, options.stacktrace).interpret().map(|_| ()).map_err(|e| {
+        print_vm_error(&e);
+        Failure::Runtime
+    })
+}
+
+// Compile a file and list the constant pool of the chunk, one constant per line with its index and type
+fn dump_constants(filename: &str) -> std::result::Result<(), Failure> {
+    let chunk = compile_file_to_chunk(filename)?;
+
+    for (index, constant) in chunk.constants().iter().enumerate() {
+        let kind = match constant {
+            Constant::Integer(_) => "INT",
+            Constant::Float(_) => "FLOAT",
+            Constant::Bool(_) => "BOOL",
+            Constant::Nil => "NIL",
+            Constant::Function(_) | Constant::Closure(_) => "FUN",
         };
+        println!("{:04}\t{}\t{}", index, kind, constant);
     }
+    Ok(())
+}
+
+// Compile a file and write the chunk to disk, so it can be run later with --run-chunk without compiling it again
+fn compile_file(filename: &str, output: &str) -> std::result::Result<(), Failure> {
+    let chunk = compile_file_to_chunk(filename)?;
+
+    fs::write(output, chunk.to_bytes()).map_err(|e| {
+        println!("[\x1b[91mERR\x1b[0m] Can't write '{}': {}", output, e);
+        Failure::CannotCreate
+    })
+}
+
+fn run_chunk_file(filename: &str, options: VmOptions) -> std::result::Result<(), Failure> {
+    let bytes = fs::read(filename).map_err(|e| {
+        println!("[\x1b[91mERR\x1b[0m] Can't read '{}': {}", filename, e);
+        Failure::NoInput
+    })?;
+    let chunk = Chunk::from_bytes(&bytes).map_err(|e| {
+        println!("[\x1b[91mERR\x1b[0m] {}", e);
+        Failure::InvalidCode
+    })?;
+
+    run_chunk(&chunk, options)
+}
 
-    println!("There was an error in the tokenizer: {}", lexer.has_error);
-}
\ No newline at end of file
+// Calculator mode, compiles a single arithmetic expression to bytecode and prints what the VM computes
+fn calculate(expression: &str) -> std::result::Result<(), Failure> {
+    let tokens = tokenize(expression).ok_or(Failure::InvalidCode)?;
+    let mut parser = Parser::new(&tokens);
+    parser.set_source(expression);
+    let expression = parser.parse_expression().map_err(|e| {
+        print_parser_error(&e);
+        Failure::InvalidCode
+    })?;
+    let chunk = Compiler::new().compile_expr(&expression).map_err(|e| {
+        print_compiler_error(&e);
+        Failure::InvalidCode
+    })?;
+
+    match VM::from_chunk(&chunk, false, false).interpret() {
+        Ok(Some(result)) => println!("{}", result),
+        Ok(None) => {}
+        Err(e) => {
+            print_vm_error(&e);
+            return Err(Failure::Runtime);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Maps are printed sorted by key, so the random order of the HashMap underneath never shows
+    #[test]
+    fn printing_a_map_gives_the_same_output_both_runs() {
+        let statements = parse("
+            var scores = {\"k\": 11, \"b\": 2, \"h\": 8, \"a\": 1, \"f\": 6, \"c\": 3, \"j\": 10, \"e\": 5};
+            scores[\"d\"] = 4;
+            scores[\"g\"] = 7;
+            print scores;
+            print repr(scores), len(scores);
+        ").unwrap();
+
+        let (first, second) = run_twice(&statements);
+        assert_eq!(first, second);
+        assert_eq!(first.1, None);
+        assert!(first.0.starts_with("{a: 1, b: 2, c: 3, d: 4, e: 5, f: 6, g: 7, h: 8, j: 10, k: 11}\n"));
+    }
+
+    #[test]
+    fn a_brace_at_the_prompt_starts_a_block() {
+        assert!(!is_lone_expression("{}"));
+        assert!(!is_lone_expression("  { print 1; }"));
+        assert!(is_lone_expression("({})"));
+        assert!(is_lone_expression("1 + 2"));
+    }
+}
@@ -1,49 +1,947 @@
+use crate::errors::{ErrorCode, InvalidTokenError, ParserError};
+use crate::input_stream::InputStream;
+use crate::expressions::{Expr, LiteralValue};
 use crate::lexer::*;
+use crate::statements::Stmt;
 
-struct Parser<'a> {
+// Statement keywords the parser can safely restart from after an error
+const STATEMENT_KEYWORDS: &[&str] = &["var", "fun", "if", "while", "for", "break", "continue", "print", "return"];
+
+// How deep expressions and blocks can nest before the parser gives up instead of overflowing the stack
+pub const DEFAULT_MAX_DEPTH: usize = 256;
+
+// The bytecode stores argument counts in a single byte, so calls and declarations can't have more than this
+const MAX_ARGUMENTS: usize = 255;
+
+// The grammar the parser implements, printed by --grammar. Every rule is named after the method that parses it,
+// so a change to one of those methods has to be made here too. nested_statement only exists to count nesting and
+// is part of statement
+pub const GRAMMAR: &str = r#"program                → declaration* EOF ;
+
+declaration            → function_declaration | var_declaration | statement ;
+function_declaration   → "fun" IDENTIFIER function_rest ;
+function_rest          → "(" ( IDENTIFIER ( "," IDENTIFIER )* )? ")" "{" block ;
+var_declaration        → "var" IDENTIFIER ( "=" expression )? ";" ;
+
+statement              → print_statement | if_statement | while_statement | for_statement
+                       | return_statement | loop_control_statement | "{" block | expression_statement ;
+print_statement        → "print" expression ( "," expression )* ";" ;
+if_statement           → "if" expression statement ( "elif" expression statement )* ( "else" statement )? ;
+while_statement        → "while" expression loop_body ;
+for_statement          → "for" "(" ( var_declaration | expression_statement | ";" )
+                         expression? ";" expression? ")" loop_body ;
+loop_body              → statement ;
+return_statement       → "return" expression? ";" ;
+loop_control_statement → ( "break" | "continue" ) ";" ;
+block                  → declaration* "}" ;
+expression_statement   → expression ";" ;
+
+expression             → assignment ;
+assignment             → ternary ( "=" assignment )? ;
+ternary                → or ( "?" expression ":" ternary )? ;
+or                     → and ( "or" and )* ;
+and                    → equality ( "and" equality )* ;
+equality               → comparison ( ( "!=" | "==" ) comparison )* ;
+comparison             → shift ( ( ">" | ">=" | "<" | "<=" ) shift )* ;
+shift                  → term ( ( "<<" | ">>" ) term )* ;
+term                   → factor ( ( "-" | "+" ) factor )* ;
+factor                 → unary ( ( "/" | "*" | "%" ) unary )* ;
+unary                  → ( "!" | "-" ) unary | call ;
+call                   → primary ( "(" finish_call | "[" expression "]" )* ;
+finish_call            → ( expression ( "," expression )* )? ")" ;
+primary                → "true" | "false" | "nil" | NUMBER | STRING | INTERPOLATION | BYTES | IDENTIFIER
+                       | "[" ( expression ( "," expression )* )? "]"
+                       | "{" ( expression ":" expression ( "," expression ":" expression )* )? "}"
+                       | ( "lambda" | "λ" ) function_rest
+                       | "(" expression ( "," expression )* ")" ;
+
+The target of an assignment can only be a variable or an index, and a '{' at the start of a statement is always
+a block, a map there has to be put in parentheses. An INTERPOLATION is a string with `${` expression `}` in it.
+"#;
+
+pub struct Parser<'a> {
     current: usize,
-    token_vector: &'a Vec<Token>,
-    had_error: bool,
-    panic_mode: bool,
+    token_vector: &'a [Token],
+    // Source the tokens were read from, only used to show the line an error is on
+    source: &'a str,
+    depth: usize,
+    max_depth: usize,
+    // How many loops enclose the statement being parsed, break and continue are only allowed inside one
+    loop_depth: usize,
 }
 
 impl<'a> Parser<'a> {
 
-    pub fn new(token_vector: &'a Vec<Token>) -> Self {
+    // The tokens have to end with exactly one EOF, as the lexer gives them. is_at_end stops at the first one and
+    // nothing is ever read past it
+    pub fn new(token_vector: &'a [Token]) -> Self {
+        debug_assert!(
+            token_vector.iter().position(|token| token.token_type == TokenType::Eof) == token_vector.len().checked_sub(1),
+            "The token vector must end with a single EOF token"
+        );
         Parser {
             current: 0,
             token_vector,
-            had_error: false,
-            panic_mode: false
+            source: "",
+            depth: 0,
+            max_depth: DEFAULT_MAX_DEPTH,
+            loop_depth: 0,
+        }
+    }
+
+    pub fn set_source(&mut self, source: &'a str) {
+        self.source = source;
+    }
+
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.max_depth = max_depth;
+    }
+
+    // Parse the whole token vector, every statement is either parsed correctly or it is an error
+    // after which the parser synchronizes and tries the next statement
+    pub fn parse(&mut self) -> Vec<Result<Stmt, ParserError>> {
+        let mut statements = Vec::new();
+        while !self.is_at_end() {
+            // An error unwinds the whole statement without leaving the nested rules, so start counting again
+            self.depth = 0;
+            self.loop_depth = 0;
+            let statement = self.declaration();
+            if statement.is_err() {
+                self.synchronize();
+            }
+            statements.push(statement);
+        }
+        statements
+    }
+
+    // Same as parse, but gives either every statement or, if any of them failed, every error
+    pub fn parse_program(&mut self) -> Result<Vec<Stmt>, Vec<ParserError>> {
+        let mut statements = Vec::new();
+        let mut errors = Vec::new();
+        for statement in self.parse() {
+            match statement {
+                Ok(statement) => statements.push(statement),
+                Err(error) => errors.push(error),
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(statements)
+        } else {
+            Err(errors)
+        }
+    }
+
+    // Parse the token vector as one lone expression, anything left after it is an error
+    pub fn parse_expression(&mut self) -> Result<Expr, ParserError> {
+        let expression = self.expression()?;
+        if !self.is_at_end() {
+            return Err(self.error_at(self.peek(), ErrorCode::UnexpectedToken, "Expected end of expression"));
+        }
+        Ok(expression)
+    }
+
+    fn declaration(&mut self) -> Result<Stmt, ParserError> {
+        if self.match_keyword("fun") {
+            return self.function_declaration();
+        }
+        if self.match_keyword("var") {
+            return self.var_declaration();
+        }
+        self.statement()
+    }
+
+    fn function_declaration(&mut self) -> Result<Stmt, ParserError> {
+        let name = self.consume(TokenType::Identifier, "Expected function name")?;
+        let (params, body) = self.function_rest("function name")?;
+        Ok(Stmt::Function { name, params, body })
+    }
+
+    // The parameter list and body shared by function declarations and lambdas, `after` is what precedes the '('
+    fn function_rest(&mut self, after: &str) -> Result<(Vec<Token>, Vec<Stmt>), ParserError> {
+        self.consume(TokenType::LeftParen, &format!("Expected '(' after {}", after))?;
+        let opener = self.previous().clone();
+        let mut params = Vec::new();
+        if !self.check(&TokenType::RightParen) {
+            loop {
+                params.push(self.consume(TokenType::Identifier, "Expected parameter name")?);
+                if !self.match_tokens(&[TokenType::Comma]) {
+                    break;
+                }
+            }
+        }
+        self.consume_closing(TokenType::RightParen, &opener, "Expected ')' after parameters")?;
+
+        // A loop around the declaration doesn't make break and continue valid inside the body
+        self.consume(TokenType::LeftBrace, "Expected '{' before function body")?;
+        let enclosing_loops = std::mem::replace(&mut self.loop_depth, 0);
+        let body = self.block();
+        self.loop_depth = enclosing_loops;
+
+        let body = body?;
+        // Checked once the whole function is parsed, so the parser doesn't resynchronize into its body
+        if let Some(extra_param) = params.get(MAX_ARGUMENTS) {
+            let message = format!("Can't have more than {} parameters", MAX_ARGUMENTS);
+            return Err(self.error_at(extra_param, ErrorCode::TooManyArguments, &message));
         }
+        Ok((params, body))
     }
 
-    pub fn compile(&self) -> bool {
+    fn var_declaration(&mut self) -> Result<Stmt, ParserError> {
+        let name = self.consume(TokenType::Identifier, "Expected variable name")?;
 
-        !self.had_error
+        // `var x;` is the same as `var x = nil;`
+        let initializer = if self.match_tokens(&[TokenType::Equal]) {
+            self.expression()?
+        } else {
+            Expr::Literal { value: LiteralValue::Nil, token: None }
+        };
+
+        self.consume(TokenType::Semicolon, "Expected ';' after variable declaration")?;
+        Ok(Stmt::Var { name, initializer })
     }
 
-    pub fn error_at(&mut self, token: &Token, message: &str) {
-        if self.panic_mode {
-            return;
+    fn statement(&mut self) -> Result<Stmt, ParserError> {
+        self.enter_nesting("Statement too deeply nested")?;
+        let statement = self.nested_statement();
+        self.depth -= 1;
+        statement
+    }
+
+    fn nested_statement(&mut self) -> Result<Stmt, ParserError> {
+        if self.match_keyword("print") {
+            return self.print_statement();
+        }
+        if self.match_keyword("if") {
+            return self.if_statement();
+        }
+        if self.match_keyword("while") {
+            return self.while_statement();
+        }
+        if self.match_keyword("for") {
+            return self.for_statement();
+        }
+        if self.match_keyword("return") {
+            return self.return_statement();
+        }
+        if self.match_keyword("break") || self.match_keyword("continue") {
+            return self.loop_control_statement();
+        }
+        if self.match_tokens(&[TokenType::LeftBrace]) {
+            return Ok(Stmt::Block { statements: self.block()? });
         }
-        self.panic_mode = true;
+        self.expression_statement()
+    }
+
+    fn print_statement(&mut self) -> Result<Stmt, ParserError> {
+        let keyword = self.previous().clone();
+        let mut expressions = vec![self.expression()?];
+        while self.match_tokens(&[TokenType::Comma]) {
+            expressions.push(self.expression()?);
+        }
+
+        self.consume(TokenType::Semicolon, "Expected ';' after value")?;
+        Ok(Stmt::Print { keyword, expressions })
+    }
+
+    fn return_statement(&mut self) -> Result<Stmt, ParserError> {
+        let keyword = self.previous().clone();
+
+        let value = if self.check(&TokenType::Semicolon) {
+            None
+        } else {
+            Some(self.expression()?)
+        };
 
-        println!("Error at {}:{}", token.line, token.col);
-        println!("{}", message);
-        self.had_error = true;
+        self.consume(TokenType::Semicolon, "Expected ';' after return value")?;
+        Ok(Stmt::Return { keyword, value })
     }
 
-    pub fn advance(&mut self) {
-        self.current += 1;
+    // break and continue, the keyword was just consumed
+    fn loop_control_statement(&mut self) -> Result<Stmt, ParserError> {
+        let keyword = self.previous().clone();
+        if self.loop_depth == 0 {
+            let message = format!("Can't use '{}' outside of a loop", keyword.value);
+            return Err(self.error_at(&keyword, ErrorCode::OutsideLoop, &message));
+        }
+
+        self.consume(TokenType::Semicolon, &format!("Expected ';' after '{}'", keyword.value))?;
+        if keyword.token_type.is_keyword("break") {
+            Ok(Stmt::Break { keyword })
+        } else {
+            Ok(Stmt::Continue { keyword })
+        }
+    }
+
+    fn if_statement(&mut self) -> Result<Stmt, ParserError> {
+        let keyword = self.previous().clone();
+        let condition = self.expression()?;
+        let then_branch = Box::new(self.statement()?);
+
+        // An else followed by another if is how else if chains are made, with or without braces around the branches.
+        // The else is taken by the closest if, so in `if a if b x; else y;` it belongs to `if b`.
+        // `elif` is the same as `else if`, the rest of the chain becomes an if in the else branch
+        let else_branch = if self.match_keyword("elif") {
+            Some(Box::new(self.if_statement()?))
+        } else if self.match_keyword("else") {
+            Some(Box::new(self.statement()?))
+        } else {
+            None
+        };
+
+        Ok(Stmt::If { keyword, condition, then_branch, else_branch })
     }
 
-    pub fn consume(&mut self, token_type: TokenType, message: &str) {
-        if self.token_vector[self.current].token_type == token_type {
+    fn while_statement(&mut self) -> Result<Stmt, ParserError> {
+        let keyword = self.previous().clone();
+        let condition = self.expression()?;
+        let body = Box::new(self.loop_body()?);
+
+        Ok(Stmt::While { keyword, condition, body, increment: None })
+    }
+
+    fn loop_body(&mut self) -> Result<Stmt, ParserError> {
+        self.loop_depth += 1;
+        let body = self.statement();
+        self.loop_depth -= 1;
+        body
+    }
+
+    // There is no for statement in the AST, `for (init; cond; incr) body` is turned into
+    // `{ init; while cond body }` with incr kept in the while, so that it still runs after a continue.
+    // Any of the three clauses can be left out
+    fn for_statement(&mut self) -> Result<Stmt, ParserError> {
+        let keyword = self.previous().clone();
+        self.consume(TokenType::LeftParen, "Expected '(' after 'for'")?;
+        let opener = self.previous().clone();
+
+        let initializer = if self.match_tokens(&[TokenType::Semicolon]) {
+            None
+        } else if self.match_keyword("var") {
+            Some(self.var_declaration()?)
+        } else {
+            Some(self.expression_statement()?)
+        };
+
+        let condition = if self.check(&TokenType::Semicolon) {
+            Expr::Literal { value: LiteralValue::Bool(true), token: None }
+        } else {
+            self.expression()?
+        };
+        self.consume(TokenType::Semicolon, "Expected ';' after loop condition")?;
+
+        let increment = if self.check(&TokenType::RightParen) {
+            None
+        } else {
+            Some(Box::new(self.expression()?))
+        };
+        self.consume_closing(TokenType::RightParen, &opener, "Expected ')' after for clauses")?;
+
+        let mut body = Stmt::While { keyword, condition, body: Box::new(self.loop_body()?), increment };
+        if let Some(initializer) = initializer {
+            body = Stmt::Block { statements: vec![initializer, body] };
+        }
+
+        Ok(body)
+    }
+
+    // Called right after the opening brace was consumed
+    fn block(&mut self) -> Result<Vec<Stmt>, ParserError> {
+        let opener = self.previous().clone();
+
+        let mut statements = Vec::new();
+        while !self.check(&TokenType::RightBrace) && !self.is_at_end() {
+            statements.push(self.declaration()?);
+        }
+
+        self.consume_closing(TokenType::RightBrace, &opener, "Expected '}' after block")?;
+        Ok(statements)
+    }
+
+    fn expression_statement(&mut self) -> Result<Stmt, ParserError> {
+        let expression = self.expression()?;
+        self.consume(TokenType::Semicolon, "Expected ';' after expression")?;
+        Ok(Stmt::Expression { expression })
+    }
+
+    fn expression(&mut self) -> Result<Expr, ParserError> {
+        self.enter_nesting("Expression too deeply nested")?;
+        let expr = self.assignment();
+        self.depth -= 1;
+        expr
+    }
+
+    fn assignment(&mut self) -> Result<Expr, ParserError> {
+        let expr = self.ternary()?;
+
+        if self.match_tokens(&[TokenType::Equal]) {
+            let equals = self.previous().clone();
+            let value = self.assignment()?;
+
+            // Only variables and array elements can be assigned to, everything else is an invalid target
+            return match expr {
+                Expr::Variable { name } => Ok(Expr::Assign { name, value: Box::new(value) }),
+                Expr::Index { target, bracket, index } => {
+                    Ok(Expr::IndexAssign { target, bracket, index, value: Box::new(value) })
+                }
+                _ => Err(self.error_at(&equals, ErrorCode::InvalidAssignmentTarget, "Invalid assignment target")),
+            };
+        }
+
+        Ok(expr)
+    }
+
+    // `c ? a : b`, the else branch can be another ternary, so `a ? b : c ? d : e` is `a ? b : (c ? d : e)`
+    fn ternary(&mut self) -> Result<Expr, ParserError> {
+        let condition = self.or()?;
+
+        if self.match_tokens(&[TokenType::Question]) {
+            let question = self.previous().clone();
+            let then_branch = self.expression()?;
+            self.consume(TokenType::Colon, "Expected ':' after the then branch of a ternary")?;
+
+            self.enter_nesting("Expression too deeply nested")?;
+            let else_branch = self.ternary();
+            self.depth -= 1;
+
+            return Ok(Expr::Ternary {
+                condition: Box::new(condition),
+                question,
+                then_branch: Box::new(then_branch),
+                else_branch: Box::new(else_branch?),
+            });
+        }
+
+        Ok(condition)
+    }
+
+    fn or(&mut self) -> Result<Expr, ParserError> {
+        let mut expr = self.and()?;
+
+        while self.match_keyword("or") {
+            let operator = self.previous().clone();
+            let right = self.and()?;
+            expr = Expr::Logical { left: Box::new(expr), operator, right: Box::new(right) };
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, ParserError> {
+        let mut expr = self.equality()?;
+
+        while self.match_keyword("and") {
+            let operator = self.previous().clone();
+            let right = self.equality()?;
+            expr = Expr::Logical { left: Box::new(expr), operator, right: Box::new(right) };
+        }
+        Ok(expr)
+    }
+
+    fn equality(&mut self) -> Result<Expr, ParserError> {
+        let mut expr = self.comparison()?;
+
+        while self.match_tokens(&[TokenType::BangEqual, TokenType::EqualEqual]) {
+            let operator = self.previous().clone();
+            let right = self.comparison()?;
+            expr = Expr::Binary { left: Box::new(expr), operator, right: Box::new(right) };
+        }
+        Ok(expr)
+    }
+
+    fn comparison(&mut self) -> Result<Expr, ParserError> {
+        let mut expr = self.shift()?;
+
+        while self.match_tokens(&[TokenType::Greater, TokenType::GreaterEqual, TokenType::Less, TokenType::LessEqual]) {
+            let operator = self.previous().clone();
+            let right = self.shift()?;
+            expr = Expr::Binary { left: Box::new(expr), operator, right: Box::new(right) };
+        }
+        Ok(expr)
+    }
+
+    fn shift(&mut self) -> Result<Expr, ParserError> {
+        let mut expr = self.term()?;
+
+        while self.match_tokens(&[TokenType::LessLess, TokenType::GreaterGreater]) {
+            let operator = self.previous().clone();
+            let right = self.term()?;
+            expr = Expr::Binary { left: Box::new(expr), operator, right: Box::new(right) };
+        }
+        Ok(expr)
+    }
+
+    fn term(&mut self) -> Result<Expr, ParserError> {
+        let mut expr = self.factor()?;
+
+        while self.match_tokens(&[TokenType::Minus, TokenType::Plus]) {
+            let operator = self.previous().clone();
+            let right = self.factor()?;
+            expr = Expr::Binary { left: Box::new(expr), operator, right: Box::new(right) };
+        }
+        Ok(expr)
+    }
+
+    fn factor(&mut self) -> Result<Expr, ParserError> {
+        let mut expr = self.unary()?;
+
+        while self.match_tokens(&[TokenType::Slash, TokenType::Star, TokenType::Percent]) {
+            let operator = self.previous().clone();
+            let right = self.unary()?;
+            expr = Expr::Binary { left: Box::new(expr), operator, right: Box::new(right) };
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, ParserError> {
+        if self.match_tokens(&[TokenType::Bang, TokenType::Minus]) {
+            let operator = self.previous().clone();
+            self.enter_nesting("Expression too deeply nested")?;
+            let right = self.unary();
+            self.depth -= 1;
+            return Ok(Expr::Unary { operator, right: Box::new(right?) });
+        }
+        self.call()
+    }
+
+    // Every pair of parentheses after an expression calls whatever the expression before it evaluates to, and
+    // calls and indexing can follow each other in any order, so `f()()`, `a[0]()` and `(lambda () {})()` all work
+    fn call(&mut self) -> Result<Expr, ParserError> {
+        let mut expr = self.primary()?;
+
+        loop {
+            if self.match_tokens(&[TokenType::LeftParen]) {
+                expr = self.finish_call(expr)?;
+            } else if self.match_tokens(&[TokenType::LeftBracket]) {
+                let bracket = self.previous().clone();
+                let index = self.expression()?;
+                self.consume_closing(TokenType::RightBracket, &bracket, "Expected ']' after index")?;
+                expr = Expr::Index { target: Box::new(expr), bracket, index: Box::new(index) };
+            } else {
+                break;
+            }
+        }
+        Ok(expr)
+    }
+
+    fn finish_call(&mut self, callee: Expr) -> Result<Expr, ParserError> {
+        let opener = self.previous().clone();
+        let mut arguments = Vec::new();
+        // The limit is only reported once the call is parsed, so the rest of it isn't reported as more errors
+        let mut extra_argument = None;
+        if !self.check(&TokenType::RightParen) {
+            loop {
+                if arguments.len() == MAX_ARGUMENTS && extra_argument.is_none() {
+                    extra_argument = Some(self.peek().clone());
+                }
+                arguments.push(self.expression()?);
+                if !self.match_tokens(&[TokenType::Comma]) {
+                    break;
+                }
+            }
+        }
+        let paren = self.consume_closing(TokenType::RightParen, &opener, "Expected ')' after arguments")?;
+        if let Some(extra_argument) = extra_argument {
+            let message = format!("Can't have more than {} arguments", MAX_ARGUMENTS);
+            return Err(self.error_at(&extra_argument, ErrorCode::TooManyArguments, &message));
+        }
+
+        Ok(Expr::Call { callee: Box::new(callee), paren, arguments })
+    }
+
+    fn primary(&mut self) -> Result<Expr, ParserError> {
+        if self.match_keyword("true") {
+            return Ok(self.literal(LiteralValue::Bool(true)));
+        }
+        if self.match_keyword("false") {
+            return Ok(self.literal(LiteralValue::Bool(false)));
+        }
+        if self.match_keyword("nil") {
+            return Ok(self.literal(LiteralValue::Nil));
+        }
+
+        // Until integers are a type of their own, every number is a float
+        if self.match_tokens(&[TokenType::Integer, TokenType::Numeral]) {
+            let number = self.previous();
+            return match number.value.parse::<f64>() {
+                Ok(value) => Ok(self.literal(LiteralValue::Number(value))),
+                Err(_) => Err(self.error_at(number, ErrorCode::InvalidLiteral, "Invalid number literal")),
+            };
+        }
+        if self.match_tokens(&[TokenType::String]) {
+            return Ok(self.literal(LiteralValue::Text(self.previous().value.clone())));
+        }
+        if self.match_tokens(&[TokenType::Interpolation]) {
+            let string = self.previous().clone();
+            return self.interpolation(&string);
+        }
+        if self.match_tokens(&[TokenType::Bytes]) {
+            let bytes = self.previous();
+            return match unescape_bytes(&bytes.value) {
+                Ok(value) => Ok(self.literal(LiteralValue::Bytes(value))),
+                Err(message) => Err(self.error_at(bytes, ErrorCode::InvalidLiteral, message)),
+            };
+        }
+        if self.match_tokens(&[TokenType::Identifier]) {
+            return Ok(Expr::Variable { name: self.previous().clone() });
+        }
+        if self.match_tokens(&[TokenType::LeftBracket]) {
+            let bracket = self.previous().clone();
+            let mut elements = Vec::new();
+            if !self.check(&TokenType::RightBracket) {
+                loop {
+                    elements.push(self.expression()?);
+                    if !self.match_tokens(&[TokenType::Comma]) {
+                        break;
+                    }
+                }
+            }
+            self.consume_closing(TokenType::RightBracket, &bracket, "Expected ']' after array elements")?;
+            return Ok(Expr::ArrayLiteral { bracket, elements });
+        }
+        // A '{' is only a map here, where an expression is expected. At the start of a statement it is always a
+        // block, since nested_statement() checks for it before trying an expression statement, so a map can only
+        // start a statement if it's wrapped in parentheses
+        if self.match_tokens(&[TokenType::LeftBrace]) {
+            let brace = self.previous().clone();
+            let mut entries = Vec::new();
+            if !self.check(&TokenType::RightBrace) {
+                loop {
+                    let key = self.expression()?;
+                    self.consume(TokenType::Colon, "Expected ':' after map key")?;
+                    entries.push((key, self.expression()?));
+                    if !self.match_tokens(&[TokenType::Comma]) {
+                        break;
+                    }
+                }
+            }
+            self.consume_closing(TokenType::RightBrace, &brace, "Expected '}' after map entries")?;
+            return Ok(Expr::MapLiteral { brace, entries });
+        }
+        if self.match_keyword("lambda") || self.match_keyword("λ") {
+            let (params, body) = self.function_rest("lambda")?;
+            return Ok(Expr::Lambda { params, body });
+        }
+
+        if self.match_tokens(&[TokenType::LeftParen]) {
+            let paren = self.previous().clone();
+            let expression = self.expression()?;
+
+            // A comma after the first expression makes it a tuple instead of a grouping
+            if self.match_tokens(&[TokenType::Comma]) {
+                let mut elements = vec![expression];
+                loop {
+                    elements.push(self.expression()?);
+                    if !self.match_tokens(&[TokenType::Comma]) {
+                        break;
+                    }
+                }
+                self.consume_closing(TokenType::RightParen, &paren, "Expected ')' after tuple elements")?;
+                return Ok(Expr::Tuple { paren, elements });
+            }
+
+            self.consume_closing(TokenType::RightParen, &paren, "Expected ')' after expression")?;
+            return Ok(Expr::Grouping { expression: Box::new(expression) });
+        }
+
+        Err(self.error_at(self.peek(), ErrorCode::ExpectedExpression, "Expected expression"))
+    }
+
+    // The code of every `${}` is lexed and parsed on its own, starting from where it is in the source so tokens
+    // and errors have the right positions
+    fn interpolation(&mut self, string: &Token) -> Result<Expr, ParserError> {
+        let mut parts = Vec::new();
+        for segment in split_interpolation(&string.value) {
+            let (source, offset) = match segment {
+                Segment::Text(text) => {
+                    parts.push(Expr::Literal { value: LiteralValue::Text(text), token: None });
+                    continue;
+                }
+                Segment::Code { source, offset } => (source, offset),
+            };
+            if source.trim().is_empty() {
+                return Err(self.error_at(string, ErrorCode::ExpectedExpression, "Expected an expression inside '${}'"));
+            }
+
+            let mut input_stream = InputStream::new(&source);
+            (input_stream.line, input_stream.col) = code_position(string, offset);
+            let tokens: Vec<Token> = TokenStream::new(&mut input_stream)
+                .collect::<Result<_, _>>()
+                .map_err(|e: InvalidTokenError| ParserError {
+                    code: e.code,
+                    message: e.message,
+                    line_as_string: self.source.lines().nth(e.line.saturating_sub(1)).unwrap_or("").to_string(),
+                    line: e.line,
+                    col: e.col,
+                })?;
+
+            let mut parser = Parser::new(&tokens);
+            parser.set_source(self.source);
+            parser.set_max_depth(self.max_depth.saturating_sub(self.depth));
+            parts.push(parser.parse_expression()?);
+        }
+        Ok(Expr::Interpolation { string: string.clone(), parts })
+    }
+
+    // Literal made from the token that was just consumed, keeping its lexeme and position
+    fn literal(&self, value: LiteralValue) -> Expr {
+        Expr::Literal { value, token: Some(Box::new(self.previous().clone())) }
+    }
+
+    // After an error, discard tokens until we are at the probable start of the next statement
+    fn synchronize(&mut self) {
+        self.advance();
+
+        while !self.is_at_end() {
+            if self.previous().token_type == TokenType::Semicolon {
+                return;
+            }
+
+            if let TokenType::Keyword(keyword) = &self.peek().token_type {
+                if STATEMENT_KEYWORDS.contains(&keyword.as_str()) {
+                    return;
+                }
+            }
+
+            self.advance();
+        }
+    }
+
+    fn enter_nesting(&mut self, message: &str) -> Result<(), ParserError> {
+        self.depth += 1;
+        if self.depth > self.max_depth {
+            return Err(self.error_at(self.peek(), ErrorCode::NestingTooDeep, message));
+        }
+        Ok(())
+    }
+
+    fn error_at(&self, token: &Token, code: ErrorCode, message: &str) -> ParserError {
+        ParserError {
+            code,
+            message: message.to_string(),
+            line_as_string: self.source.lines().nth(token.line.saturating_sub(1)).unwrap_or("").to_string(),
+            line: token.line,
+            col: token.col,
+        }
+    }
+
+    fn match_tokens(&mut self, token_types: &[TokenType]) -> bool {
+        for token_type in token_types {
+            if self.check(token_type) {
+                self.advance();
+                return true;
+            }
+        }
+        false
+    }
+
+    fn match_keyword(&mut self, keyword: &str) -> bool {
+        if self.peek().token_type.is_keyword(keyword) {
             self.advance();
-            return;
+            return true;
+        }
+        false
+    }
+
+    fn consume(&mut self, token_type: TokenType, message: &str) -> Result<Token, ParserError> {
+        if self.check(&token_type) {
+            return Ok(self.advance().clone());
         }
-        
-        self.error_at(&self.token_vector[self.current], message);
+
+        Err(self.error_at(self.peek(), ErrorCode::UnexpectedToken, message))
     }
-}
\ No newline at end of file
+
+    // Like consume, but on failure also points back at the delimiter that was left open
+    fn consume_closing(&mut self, token_type: TokenType, opener: &Token, message: &str) -> Result<Token, ParserError> {
+        self.consume(token_type, message).map_err(|mut error| {
+            error.message = format!(
+                "{}, unclosed '{}' opened at {}:{}",
+                error.message, opener.value, opener.line, opener.col
+            );
+            error
+        })
+    }
+
+    fn check(&self, token_type: &TokenType) -> bool {
+        !self.is_at_end() && self.peek().token_type == *token_type
+    }
+
+    fn advance(&mut self) -> &Token {
+        if !self.is_at_end() {
+            self.current += 1;
+        }
+        self.previous()
+    }
+
+    fn is_at_end(&self) -> bool {
+        self.peek().token_type == TokenType::Eof
+    }
+
+    fn peek(&self) -> &Token {
+        &self.token_vector[self.current]
+    }
+
+    fn previous(&self) -> &Token {
+        &self.token_vector[self.current - 1]
+    }
+}
+
+// Line and column where the code of a `${}` starts, `offset` characters into the string. The token is at the opening
+// quote, so the column counts from there unless there is a line break before the code
+fn code_position(string: &Token, offset: usize) -> (usize, usize) {
+    let chars: Vec<char> = string.value.chars().collect();
+    let line = string.line + chars[..offset].iter().filter(|&&ch| ch == '\n').count();
+    match chars[..offset].iter().rposition(|&ch| ch == '\n') {
+        Some(newline) => (line, offset - newline),
+        None => (line, string.col + 1 + offset),
+    }
+}
+
+// `\xNN` is the byte with that hex value, any other character after a backslash is kept as it is, like in strings
+fn unescape_bytes(text: &str) -> Result<Vec<u8>, &'static str> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(ch) = chars.next() {
+        let ch = match ch {
+            '\\' => match chars.next() {
+                Some('x') => {
+                    let digits: String = chars.by_ref().take(2).collect();
+                    match u8::from_str_radix(&digits, 16) {
+                        Ok(byte) if digits.len() == 2 => {
+                            bytes.push(byte);
+                            continue;
+                        }
+                        _ => return Err("Expected two hex digits after '\\x'"),
+                    }
+                }
+                Some(escaped) => escaped,
+                None => return Err("Unfinished escape at the end of the byte string"),
+            },
+            ch => ch,
+        };
+        if !ch.is_ascii() {
+            return Err("Byte strings can only contain ASCII characters, use '\\x' for anything else");
+        }
+        bytes.push(ch as u8);
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast_printer::AstPrinter;
+
+    fn parse(source: &str) -> Result<Vec<Stmt>, Vec<ParserError>> {
+        let mut input_stream = InputStream::new(source);
+        let tokens: Vec<Token> = TokenStream::new(&mut input_stream).collect::<Result<_, _>>().unwrap();
+        Parser::new(&tokens).parse_program()
+    }
+
+    fn parse_to_ast(source: &str) -> Vec<String> {
+        let mut printer = AstPrinter;
+        parse(source).unwrap().iter().map(|statement| printer.print_stmt(statement)).collect()
+    }
+
+    #[test]
+    fn print_takes_several_values() {
+        assert_eq!(parse_to_ast("print 1;"), ["(print 1)"]);
+        assert_eq!(parse_to_ast("print \"x =\", 1 + 2, nil;"), ["(print \"x =\" (+ 1 2) nil)"]);
+    }
+
+    #[test]
+    fn print_values_need_a_comma_between_them() {
+        assert!(parse("print 1 2;").is_err());
+        assert!(parse("print 1,;").is_err());
+        assert!(parse("print;").is_err());
+    }
+
+    #[test]
+    fn an_error_on_the_first_line_is_on_line_1() {
+        let errors = parse("print 1 +;\nprint 2;").unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!((errors[0].line, errors[0].col), (1, 10));
+    }
+
+    // The tokens are built directly, lexing this much input would take far longer than parsing it
+    fn nested(open: TokenType, inner: &[TokenType], close: Option<TokenType>, depth: usize) -> Vec<Token> {
+        let mut token_types = vec![open; depth];
+        token_types.extend_from_slice(inner);
+        token_types.extend(close.into_iter().flat_map(|close| vec![close; depth]));
+        token_types.push(TokenType::Eof);
+        token_types.into_iter().enumerate().map(|(col, token_type)| Token::new(token_type, "", 1, col + 1)).collect()
+    }
+
+    #[test]
+    fn deeply_nested_input_is_an_error_not_a_stack_overflow() {
+        // DEFAULT_MAX_DEPTH fits the 8 MiB stack of the main thread in a debug build, test threads only get 2 MiB
+        let stack_size = 8 * 1024 * 1024;
+        std::thread::Builder::new().stack_size(stack_size).spawn(parse_deeply_nested_input).unwrap().join().unwrap();
+    }
+
+    fn parse_deeply_nested_input() {
+        // Without the limit this many levels overflow any stack
+        let depth = 100_000;
+        let parens = nested(TokenType::LeftParen, &[TokenType::Integer], Some(TokenType::RightParen), depth);
+        let error = Parser::new(&parens).parse_expression().unwrap_err();
+        assert_eq!(error.code, ErrorCode::NestingTooDeep);
+        assert_eq!(error.message, "Expression too deeply nested");
+        assert_eq!((error.line, error.col), (1, DEFAULT_MAX_DEPTH + 1));
+
+        let negations = nested(TokenType::Bang, &[TokenType::Integer], None, depth);
+        assert_eq!(Parser::new(&negations).parse_expression().unwrap_err().code, ErrorCode::NestingTooDeep);
+        let blocks = nested(TokenType::LeftBrace, &[], Some(TokenType::RightBrace), depth);
+        assert_eq!(Parser::new(&blocks).parse_program().unwrap_err()[0].code, ErrorCode::NestingTooDeep);
+
+        // Anything a person would write is still well under the limit
+        let source = format!("print {}1{};", "(".repeat(100), ")".repeat(100));
+        assert!(parse(&source).is_ok());
+    }
+
+    // GRAMMAR is written by hand, this catches a rule method that was added, renamed or removed without it
+    #[test]
+    fn tokens_end_with_exactly_one_eof() {
+        // The lexer keeps giving EOF once the input ends, but the tokens collected from it only end with one
+        for source in ["", "   ", "// only a comment", "print 1;", "print 1;\n\n"] {
+            let mut input_stream = InputStream::new(source);
+            let tokens: Vec<Token> = TokenStream::new(&mut input_stream).collect::<Result<_, _>>().unwrap();
+            let eofs = tokens.iter().filter(|token| token.token_type == TokenType::Eof).count();
+            assert_eq!(eofs, 1, "{:?}", source);
+            assert_eq!(tokens.last().unwrap().token_type, TokenType::Eof, "{:?}", source);
+        }
+
+        let mut input_stream = InputStream::new("");
+        let tokens: Vec<Token> = TokenStream::new(&mut input_stream).collect::<Result<_, _>>().unwrap();
+        assert_eq!(tokens.len(), 1);
+        assert!(Parser::new(&tokens).parse_program().unwrap().is_empty());
+    }
+
+    #[test]
+    fn grammar_has_a_rule_for_every_rule_method() {
+        let code = include_str!("parser.rs");
+        let code = &code[..code.find("#[cfg(test)]").unwrap()];
+        // The methods that parse part of the program, not the helpers that consume or look at tokens
+        let results = ["Result<Stmt,", "Result<Expr,", "Result<Vec<Stmt>,", "Result<(Vec<Token>,"];
+        let methods: Vec<&str> = code
+            .lines()
+            .filter(|line| line.starts_with("    fn ") && line.contains("(&mut self"))
+            .filter(|line| results.iter().any(|result| line.contains(result)))
+            .map(|line| &line["    fn ".len()..line.find('(').unwrap()])
+            // Only counts nesting for statement, and parses the code of a `${}` with a parser of its own
+            .filter(|method| !["nested_statement", "interpolation"].contains(method))
+            .collect();
+        let rules: Vec<&str> = GRAMMAR
+            .lines()
+            .filter_map(|line| line.split_once('→'))
+            .map(|(rule, _)| rule.trim())
+            .filter(|rule| !rule.is_empty())
+            .collect();
+
+        assert!(methods.contains(&"primary") && methods.contains(&"declaration"));
+        for method in &methods {
+            assert!(rules.contains(method), "{} has no rule in GRAMMAR", method);
+        }
+        for rule in &rules {
+            assert!(*rule == "program" || methods.contains(rule), "GRAMMAR has {} but there is no such method", rule);
+        }
+    }
+}
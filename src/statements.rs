@@ -0,0 +1,96 @@
+use crate::expressions::Expr;
+use crate::lexer::Token;
+
+#[derive(Clone, Debug)]
+pub enum Stmt {
+    Block {
+        statements: Vec<Stmt>,
+    },
+    Break {
+        keyword: Token,
+    },
+    Continue {
+        keyword: Token,
+    },
+    Expression {
+        expression: Expr,
+    },
+    Function {
+        name: Token,
+        params: Vec<Token>,
+        body: Vec<Stmt>,
+    },
+    If {
+        keyword: Token,
+        condition: Expr,
+        then_branch: Box<Stmt>,
+        else_branch: Option<Box<Stmt>>,
+    },
+    Print {
+        keyword: Token,
+        expressions: Vec<Expr>,
+    },
+    Return {
+        keyword: Token,
+        value: Option<Expr>,
+    },
+    Var {
+        name: Token,
+        initializer: Expr,
+    },
+    // The increment is only there for loops, it runs after every iteration, including the ones cut short by continue.
+    // The keyword is `for` for those
+    While {
+        keyword: Token,
+        condition: Expr,
+        body: Box<Stmt>,
+        increment: Option<Box<Expr>>,
+    },
+}
+
+// Every pass over the statements of a program implements this trait
+pub trait StmtVisitor<T> {
+    fn visit_block_stmt(&mut self, statements: &[Stmt]) -> T;
+    fn visit_break_stmt(&mut self, keyword: &Token) -> T;
+    fn visit_continue_stmt(&mut self, keyword: &Token) -> T;
+    fn visit_expression_stmt(&mut self, expression: &Expr) -> T;
+    fn visit_function_stmt(&mut self, name: &Token, params: &[Token], body: &[Stmt]) -> T;
+    fn visit_if_stmt(&mut self, keyword: &Token, condition: &Expr, then_branch: &Stmt, else_branch: Option<&Stmt>) -> T;
+    fn visit_print_stmt(&mut self, keyword: &Token, expressions: &[Expr]) -> T;
+    fn visit_return_stmt(&mut self, keyword: &Token, value: Option<&Expr>) -> T;
+    fn visit_var_stmt(&mut self, name: &Token, initializer: &Expr) -> T;
+    fn visit_while_stmt(&mut self, keyword: &Token, condition: &Expr, body: &Stmt, increment: Option<&Expr>) -> T;
+}
+
+impl Stmt {
+    pub fn accept<T>(&self, visitor: &mut dyn StmtVisitor<T>) -> T {
+        match self {
+            Stmt::Block { statements } => visitor.visit_block_stmt(statements),
+            Stmt::Break { keyword } => visitor.visit_break_stmt(keyword),
+            Stmt::Continue { keyword } => visitor.visit_continue_stmt(keyword),
+            Stmt::Expression { expression } => visitor.visit_expression_stmt(expression),
+            Stmt::Function { name, params, body } => visitor.visit_function_stmt(name, params, body),
+            Stmt::If { keyword, condition, then_branch, else_branch } => {
+                visitor.visit_if_stmt(keyword, condition, then_branch, else_branch.as_deref())
+            }
+            Stmt::Print { keyword, expressions } => visitor.visit_print_stmt(keyword, expressions),
+            Stmt::Return { keyword, value } => visitor.visit_return_stmt(keyword, value.as_ref()),
+            Stmt::Var { name, initializer } => visitor.visit_var_stmt(name, initializer),
+            Stmt::While { keyword, condition, body, increment } => {
+                visitor.visit_while_stmt(keyword, condition, body, increment.as_deref())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Same as for Expr, a variant that grows past this should box its fields instead of raising the limit. The
+    // while loop is the biggest one, with its keyword and condition kept inline since every loop has both
+    #[test]
+    fn stmt_stays_small() {
+        assert!(std::mem::size_of::<Stmt>() <= 200, "Stmt is {} bytes", std::mem::size_of::<Stmt>());
+    }
+}
@@ -0,0 +1,254 @@
+use crate::expressions::{Expr, ExprVisitor, LiteralValue};
+use crate::lexer::Token;
+use crate::statements::{Stmt, StmtVisitor};
+
+// Turns the AST back into Wolff source code that parses to the same tree
+pub struct Unparser {
+    // Current nesting level of blocks, each one is indented by 4 more spaces
+    indent: usize,
+}
+
+impl Default for Unparser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Unparser {
+    pub fn new() -> Self {
+        Unparser { indent: 0 }
+    }
+
+    // Source for a value, functions are written out as lambdas since a value has no name to declare
+    pub fn unparse_value(&mut self, value: &LiteralValue) -> String {
+        match value {
+            LiteralValue::Number(number) => unparse_number(*number),
+            LiteralValue::Text(text) => quote(text),
+            LiteralValue::Bytes(_) | LiteralValue::Bool(_) | LiteralValue::Nil => value.to_string(),
+            LiteralValue::Function(function) => self.visit_lambda_expr(&function.params, &function.body),
+            // Natives have no source, the name refers to the one the interpreter defines
+            LiteralValue::NativeFunction(function) => function.name.clone(),
+            LiteralValue::Array(elements) => {
+                let elements: Vec<String> = elements.borrow().iter().map(|element| self.unparse_value(element)).collect();
+                format!("[{}]", elements.join(", "))
+            }
+            LiteralValue::Map(map) => {
+                let map = map.borrow();
+                let mut keys: Vec<&String> = map.keys().collect();
+                keys.sort();
+                let entries: Vec<String> =
+                    keys.iter().map(|key| format!("{}: {}", quote(key), self.unparse_value(&map[*key]))).collect();
+                format!("{{{}}}", entries.join(", "))
+            }
+            LiteralValue::Tuple(elements) => {
+                let elements: Vec<String> = elements.iter().map(|element| self.unparse_value(element)).collect();
+                format!("({})", elements.join(", "))
+            }
+        }
+    }
+
+    // Declaration that defines `name` as `value` again, functions that still have their own name become `fun`
+    pub fn unparse_definition(&mut self, name: &str, value: &LiteralValue) -> String {
+        match value {
+            LiteralValue::Function(function) if function.name() == name && function.name.is_some() => {
+                format!("fun {}({}) {}", name, Self::params(&function.params), self.block(&function.body))
+            }
+            _ => format!("var {} = {};", name, self.unparse_value(value)),
+        }
+    }
+
+    fn block(&mut self, statements: &[Stmt]) -> String {
+        if statements.is_empty() {
+            return String::from("{}");
+        }
+
+        self.indent += 1;
+        let mut output = String::from("{\n");
+        for statement in statements {
+            output.push_str(&"    ".repeat(self.indent));
+            output.push_str(&statement.accept(self));
+            output.push('\n');
+        }
+        self.indent -= 1;
+        output.push_str(&"    ".repeat(self.indent));
+        output.push('}');
+        output
+    }
+
+    fn params(params: &[Token]) -> String {
+        let params: Vec<&str> = params.iter().map(|param| param.value.as_str()).collect();
+        params.join(", ")
+    }
+
+    fn list(&mut self, expressions: &[Expr]) -> String {
+        let expressions: Vec<String> = expressions.iter().map(|expression| expression.accept(self)).collect();
+        expressions.join(", ")
+    }
+}
+
+// The lexer has no escape sequences, a backslash just keeps the next character as it is
+fn quote(text: &str) -> String {
+    format!("\"{}\"", escape(text))
+}
+
+// `${` would start an interpolation, so its '$' is escaped too
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"").replace("${", "\\${")
+}
+
+// There are no literals for infinities and NaN, so they are written as the division that makes them
+fn unparse_number(number: f64) -> String {
+    if number.is_nan() {
+        String::from("(0 / 0)")
+    } else if number.is_infinite() {
+        String::from(if number > 0.0 { "(1 / 0)" } else { "(-1 / 0)" })
+    } else {
+        number.to_string()
+    }
+}
+
+impl ExprVisitor<String> for Unparser {
+    fn visit_array_literal_expr(&mut self, _bracket: &Token, elements: &[Expr]) -> String {
+        format!("[{}]", self.list(elements))
+    }
+
+    fn visit_assign_expr(&mut self, name: &Token, value: &Expr) -> String {
+        format!("{} = {}", name.value, value.accept(self))
+    }
+
+    fn visit_binary_expr(&mut self, left: &Expr, operator: &Token, right: &Expr) -> String {
+        format!("{} {} {}", left.accept(self), operator.value, right.accept(self))
+    }
+
+    fn visit_call_expr(&mut self, callee: &Expr, _paren: &Token, arguments: &[Expr]) -> String {
+        format!("{}({})", callee.accept(self), self.list(arguments))
+    }
+
+    fn visit_grouping_expr(&mut self, expression: &Expr) -> String {
+        format!("({})", expression.accept(self))
+    }
+
+    fn visit_interpolation_expr(&mut self, _string: &Token, parts: &[Expr]) -> String {
+        let mut output = String::from("\"");
+        for part in parts {
+            match part {
+                Expr::Literal { value: LiteralValue::Text(text), .. } => output.push_str(&escape(text)),
+                part => output.push_str(&format!("${{{}}}", part.accept(self))),
+            }
+        }
+        output.push('"');
+        output
+    }
+
+    fn visit_lambda_expr(&mut self, params: &[Token], body: &[Stmt]) -> String {
+        format!("lambda ({}) {}", Self::params(params), self.block(body))
+    }
+
+    fn visit_literal_expr(&mut self, value: &LiteralValue, token: Option<&Token>) -> String {
+        match (value, token) {
+            (LiteralValue::Number(_), Some(token)) => token.value.clone(),
+            _ => self.unparse_value(value),
+        }
+    }
+
+    fn visit_map_literal_expr(&mut self, _brace: &Token, entries: &[(Expr, Expr)]) -> String {
+        let entries: Vec<String> =
+            entries.iter().map(|(key, value)| format!("{}: {}", key.accept(self), value.accept(self))).collect();
+        format!("{{{}}}", entries.join(", "))
+    }
+
+    fn visit_logical_expr(&mut self, left: &Expr, operator: &Token, right: &Expr) -> String {
+        format!("{} {} {}", left.accept(self), operator.value, right.accept(self))
+    }
+
+    fn visit_index_expr(&mut self, target: &Expr, _bracket: &Token, index: &Expr) -> String {
+        format!("{}[{}]", target.accept(self), index.accept(self))
+    }
+
+    fn visit_index_assign_expr(&mut self, target: &Expr, _bracket: &Token, index: &Expr, value: &Expr) -> String {
+        let target = target.accept(self);
+        let index = index.accept(self);
+        format!("{}[{}] = {}", target, index, value.accept(self))
+    }
+
+    fn visit_tuple_expr(&mut self, _paren: &Token, elements: &[Expr]) -> String {
+        format!("({})", self.list(elements))
+    }
+
+    fn visit_ternary_expr(&mut self, condition: &Expr, _question: &Token, then_branch: &Expr, else_branch: &Expr) -> String {
+        let condition = condition.accept(self);
+        let then_branch = then_branch.accept(self);
+        format!("{} ? {} : {}", condition, then_branch, else_branch.accept(self))
+    }
+
+    fn visit_unary_expr(&mut self, operator: &Token, right: &Expr) -> String {
+        format!("{}{}", operator.value, right.accept(self))
+    }
+
+    fn visit_variable_expr(&mut self, name: &Token) -> String {
+        name.value.clone()
+    }
+}
+
+impl StmtVisitor<String> for Unparser {
+    fn visit_block_stmt(&mut self, statements: &[Stmt]) -> String {
+        self.block(statements)
+    }
+
+    fn visit_break_stmt(&mut self, _keyword: &Token) -> String {
+        String::from("break;")
+    }
+
+    fn visit_continue_stmt(&mut self, _keyword: &Token) -> String {
+        String::from("continue;")
+    }
+
+    // A statement starting with '{' would be read back as a block, so a map there is put in parentheses
+    fn visit_expression_stmt(&mut self, expression: &Expr) -> String {
+        let expression = expression.accept(self);
+        if expression.starts_with('{') {
+            format!("({});", expression)
+        } else {
+            format!("{};", expression)
+        }
+    }
+
+    fn visit_function_stmt(&mut self, name: &Token, params: &[Token], body: &[Stmt]) -> String {
+        format!("fun {}({}) {}", name.value, Self::params(params), self.block(body))
+    }
+
+    fn visit_if_stmt(&mut self, _keyword: &Token, condition: &Expr, then_branch: &Stmt, else_branch: Option<&Stmt>) -> String {
+        let mut output = format!("if {} {}", condition.accept(self), then_branch.accept(self));
+        if let Some(else_branch) = else_branch {
+            output.push_str(&format!(" else {}", else_branch.accept(self)));
+        }
+        output
+    }
+
+    fn visit_print_stmt(&mut self, _keyword: &Token, expressions: &[Expr]) -> String {
+        format!("print {};", self.list(expressions))
+    }
+
+    fn visit_return_stmt(&mut self, _keyword: &Token, value: Option<&Expr>) -> String {
+        match value {
+            Some(value) => format!("return {};", value.accept(self)),
+            None => String::from("return;"),
+        }
+    }
+
+    fn visit_var_stmt(&mut self, name: &Token, initializer: &Expr) -> String {
+        format!("var {} = {};", name.value, initializer.accept(self))
+    }
+
+    // Only for loops have an increment, so a while with one is written back as a for without an initializer
+    fn visit_while_stmt(&mut self, _keyword: &Token, condition: &Expr, body: &Stmt, increment: Option<&Expr>) -> String {
+        match increment {
+            Some(increment) => {
+                let condition = condition.accept(self);
+                let increment = increment.accept(self);
+                format!("for (; {}; {}) {}", condition, increment, body.accept(self))
+            }
+            None => format!("while {} {}", condition.accept(self), body.accept(self)),
+        }
+    }
+}
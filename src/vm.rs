@@ -1,94 +1,226 @@
+use crate::errors::{ChunkDecodeError, VMRuntimeError};
+use crate::expressions::format_number;
+
+use std::cell::RefCell;
 use std::fmt::*;
-use std::ops::{Add, Div, Mul, Neg, Sub};
+use std::io::{self, Write};
+use std::ops::{Add, Div, Mul, Neg, Rem, Sub};
+use std::rc::Rc;
+
+// How many calls can be running at once, past that the program is stopped with a stack overflow
+pub const MAX_FRAMES: usize = 256;
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Debug)]
 pub enum Constant {
     Integer(i32),
     Float(f64),
+    Bool(bool),
+    Nil,
+    // A function the way the compiler leaves it in the constant pool, CLOSURE turns it into a Closure when it runs
+    Function(Rc<CompiledFunction>),
+    Closure(Rc<Closure>),
 }
 
-impl Neg for Constant {
-    type Output = Self;
-    fn neg(self) -> Self::Output {
-        return match self {
-            Constant::Float(val) => Constant::Float(-val),
-            Constant::Integer(val) => Constant::Integer(-val),
-        };
+// The body of a function declaration or a lambda, compiled into its own chunk
+#[derive(Clone)]
+pub struct CompiledFunction {
+    // None for lambdas and for the script itself
+    pub name: Option<String>,
+    pub arity: usize,
+    // How many (is_local, index) pairs follow the CLOSURE that creates it
+    pub upvalue_count: usize,
+    pub chunk: Chunk,
+}
+
+impl Debug for CompiledFunction {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        Display::fmt(self, f)
     }
 }
 
-impl Add for Constant {
-    type Output = Self;
-    fn add(self, a: Self::Output) -> Self::Output {
-        if let Constant::Float(val_1) = self {
-            if let Constant::Float(val_2) = a {
-                return Constant::Float(val_1 + val_2);
-            }
+// Same as the tree-walker prints its functions
+impl Display for CompiledFunction {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "<fn {}>", name),
+            None => write!(f, "<lambda>"),
         }
+    }
+}
 
-        if let Constant::Integer(val_1) = self {
-            if let Constant::Integer(val_2) = a {
-                return Constant::Integer(val_1 + val_2);
+impl CompiledFunction {
+    fn write(&self, bytes: &mut Vec<u8>) {
+        bytes.push(4);
+        match &self.name {
+            Some(name) => {
+                bytes.push(1);
+                bytes.extend_from_slice(&(name.len() as u64).to_be_bytes());
+                bytes.extend_from_slice(name.as_bytes());
             }
+            None => bytes.push(0),
         }
+        bytes.extend_from_slice(&(self.arity as u64).to_be_bytes());
+        bytes.extend_from_slice(&(self.upvalue_count as u64).to_be_bytes());
+        self.chunk.write_body(bytes);
+    }
+}
+
+// A function together with the variables it captured from the functions around it
+pub struct Closure {
+    pub function: Rc<CompiledFunction>,
+    upvalues: Vec<Rc<RefCell<Upvalue>>>,
+}
 
-        dbg!(self);
-        dbg!(a);
+impl Debug for Closure {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        Display::fmt(&self.function, f)
+    }
+}
 
-        panic!("Can't add two different object types");
+// A captured variable stays on the stack while the scope that declared it is running, every closure that captured
+// it reads and writes that slot. When the scope ends the value moves into the upvalue, so the closures that outlive
+// the scope still share it
+enum Upvalue {
+    Open(usize),
+    Closed(Constant),
+}
+
+// Two constants are the same if the pool can keep one for both, so floats compare by their bits: 0 and -0 are
+// different and NaN is equal to itself. Comparing values while running uses Constant::equals instead
+impl PartialEq for Constant {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Constant::Integer(val_1), Constant::Integer(val_2)) => val_1 == val_2,
+            (Constant::Float(val_1), Constant::Float(val_2)) => val_1.to_bits() == val_2.to_bits(),
+            (Constant::Bool(val_1), Constant::Bool(val_2)) => val_1 == val_2,
+            (Constant::Nil, Constant::Nil) => true,
+            (Constant::Function(val_1), Constant::Function(val_2)) => Rc::ptr_eq(val_1, val_2),
+            (Constant::Closure(val_1), Constant::Closure(val_2)) => Rc::ptr_eq(val_1, val_2),
+            _ => false,
+        }
     }
 }
 
-impl Sub for Constant {
-    type Output = Self;
-    fn sub(self, a: Self::Output) -> Self::Output {
-        if let Constant::Float(val_1) = self && let Constant::Float(val_2) = a {
-            return Constant::Float(val_1 - val_2);
+// Only numbers can be negated, for anything else the message of the error is returned
+impl Neg for Constant {
+    type Output = std::result::Result<Self, String>;
+    fn neg(self) -> Self::Output {
+        match self {
+            Constant::Float(val) => Ok(Constant::Float(-val)),
+            Constant::Integer(val) => val.checked_neg().map(Constant::Integer).ok_or_else(|| "Integer overflow".to_string()),
+            Constant::Bool(_) => Err("Cannot negate a boolean".to_string()),
+            Constant::Nil => Err("Cannot negate nil".to_string()),
+            Constant::Function(_) | Constant::Closure(_) => Err("Cannot negate a function".to_string()),
         }
+    }
+}
 
-        if let Constant::Integer(val_1) = self && let Constant::Integer(val_2) = a {
-            return Constant::Integer(val_1 - val_2);
+impl Constant {
+    // Integers are promoted to floats when they meet one, anything that isn't a number has no float value
+    fn as_float(&self) -> Option<f64> {
+        match self {
+            Constant::Integer(val) => Some(*val as f64),
+            Constant::Float(val) => Some(*val),
+            _ => None,
         }
+    }
 
-        panic!("Can't subtract two different object types");
+    // Same rule as the tree-walker, only nil and false are falsey
+    fn is_falsey(&self) -> bool {
+        matches!(self, Constant::Nil | Constant::Bool(false))
     }
-}
 
-impl Mul for Constant {
-    type Output = Self;
-    fn mul(self, a: Self::Output) -> Self::Output {
-        if let Constant::Float(val_1) = self && let Constant::Float(val_2) = a {
-            return Constant::Float(val_1 * val_2);
+    // Numbers are equal if they have the same value, whether they are integers or floats, anything else only
+    // equals a constant of the same kind. Functions are only equal to themselves
+    fn equals(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Constant::Bool(val_1), Constant::Bool(val_2)) => val_1 == val_2,
+            (Constant::Nil, Constant::Nil) => true,
+            (Constant::Function(val_1), Constant::Function(val_2)) => Rc::ptr_eq(val_1, val_2),
+            (Constant::Closure(val_1), Constant::Closure(val_2)) => Rc::ptr_eq(val_1, val_2),
+            _ => matches!((self.as_float(), other.as_float()), (Some(val_1), Some(val_2)) if val_1 == val_2),
+        }
+    }
+
+    fn compare(&self, other: &Self, op: fn(f64, f64) -> bool) -> std::result::Result<Self, String> {
+        match (self.as_float(), other.as_float()) {
+            (Some(val_1), Some(val_2)) => Ok(Constant::Bool(op(val_1, val_2))),
+            _ => Err("Can only compare numbers".to_string()),
+        }
+    }
+
+    // Shared by the arithmetic operators, integer results that don't fit in an i32 are an error instead of wrapping
+    fn arithmetic(
+        self,
+        other: Self,
+        verb: &str,
+        integer_op: fn(i32, i32) -> Option<i32>,
+        float_op: fn(f64, f64) -> f64,
+    ) -> std::result::Result<Self, String> {
+        if let (Constant::Integer(val_1), Constant::Integer(val_2)) = (&self, &other) {
+            return integer_op(*val_1, *val_2).map(Constant::Integer).ok_or_else(|| "Integer overflow".to_string());
         }
 
-        if let Constant::Integer(val_1) = self && let Constant::Integer(val_2) = a {
-            return Constant::Integer(val_1 * val_2);
+        match (self.as_float(), other.as_float()) {
+            (Some(val_1), Some(val_2)) => Ok(Constant::Float(float_op(val_1, val_2))),
+            _ => Err(format!("Can only {} numbers", verb)),
         }
+    }
+}
+
+impl Add for Constant {
+    type Output = std::result::Result<Self, String>;
+    fn add(self, a: Self) -> Self::Output {
+        self.arithmetic(a, "add", i32::checked_add, |val_1, val_2| val_1 + val_2)
+    }
+}
 
-        panic!("Can't multiply two different object types");
+impl Sub for Constant {
+    type Output = std::result::Result<Self, String>;
+    fn sub(self, a: Self) -> Self::Output {
+        self.arithmetic(a, "subtract", i32::checked_sub, |val_1, val_2| val_1 - val_2)
+    }
+}
+
+impl Mul for Constant {
+    type Output = std::result::Result<Self, String>;
+    fn mul(self, a: Self) -> Self::Output {
+        self.arithmetic(a, "multiply", i32::checked_mul, |val_1, val_2| val_1 * val_2)
     }
 }
 
+// Only integers can't be divided by zero, floats give an infinity or NaN like they do in the tree-walker
 impl Div for Constant {
-    type Output = Self;
-    fn div(self, a: Self::Output) -> Self::Output {
-        if let Constant::Float(val_1) = self && let Constant::Float(val_2) = a {
-            return Constant::Float(val_1 / val_2);
+    type Output = std::result::Result<Self, String>;
+    fn div(self, a: Self) -> Self::Output {
+        if let (Constant::Integer(_), Constant::Integer(0)) = (&self, &a) {
+            return Err("Division by zero".to_string());
         }
+        self.arithmetic(a, "divide", i32::checked_div, |val_1, val_2| val_1 / val_2)
+    }
+}
 
-        if let Constant::Integer(val_1) = self && let Constant::Integer(val_2) = a {
-            return Constant::Integer(val_1 / val_2);
+// Same as division, only integers can't be divided by zero, a float remainder by zero is NaN
+impl Rem for Constant {
+    type Output = std::result::Result<Self, String>;
+    fn rem(self, a: Self) -> Self::Output {
+        if let (Constant::Integer(_), Constant::Integer(0)) = (&self, &a) {
+            return Err("Modulo by zero".to_string());
         }
-
-        panic!("Can't divide two different object types");
+        self.arithmetic(a, "take the remainder of", i32::checked_rem, |val_1, val_2| val_1 % val_2)
     }
 }
 
 impl Display for Constant {
     fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
         let output_string = match self {
-            Constant::Float(val) => format!("{}", val),
+            // Printed like the tree-walker prints its numbers
+            Constant::Float(val) => format_number(*val),
             Constant::Integer(val) => format!("{}", val),
+            Constant::Bool(val) => format!("{}", val),
+            Constant::Nil => "nil".to_string(),
+            Constant::Function(function) => function.to_string(),
+            Constant::Closure(closure) => closure.function.to_string(),
         };
 
         write!(f, "{}", output_string)
@@ -97,197 +229,763 @@ impl Display for Constant {
 
 pub struct VM {
     pub chunk: Chunk,
-    ip: usize,
     debug: bool,
     stacktrace: bool,
     stack: Vec<Constant>,
     //globals: Vec<Object>,
-    //frames: Vec<Frame>,
+    // The call running right now is last, the chunk itself runs in the first one
+    frames: Vec<CallFrame>,
+    // Upvalues that still point at a slot on the stack, CLOSURE reuses them so closures share their variables
+    open_upvalues: Vec<Rc<RefCell<Upvalue>>>,
+    // Where PRINT writes to
+    output: Box<dyn Write>,
+}
+
+struct CallFrame {
+    closure: Rc<Closure>,
+    ip: usize,
+    // Where the frame's slots start on the stack. In a function slot 0 is the function being called and its
+    // arguments follow, the chunk itself has no slot for that and starts with its first variable
+    base: usize,
 }
 
 #[derive(Clone)]
 pub struct Chunk {
     // Contains the bytecode program resulted from the compilation
     code: Vec<u8>,
-    // Vector for mapping bytecode to the lines of source code from which they originated
+    // Vector for mapping bytecode to the lines of source code from which they originated, run-length encoded as
+    // (offset of the first byte of the run, line) pairs
     lines_mapping_vector: Vec<(usize, usize)>,
     // Vector for compile-time constants for the program
     constant_pool: Vec<Constant>,
 }
 
-// OpCode code that holds all the information about a specific instruction in the bytecode of our VM
+impl Default for Chunk {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl Chunk {
     pub fn new() -> Self {
-        let mut new_chunk = Chunk {
+        Chunk {
             code: Vec::new(),
             lines_mapping_vector: Vec::new(),
             constant_pool: Vec::new(),
-        };
-        new_chunk.lines_mapping_vector.push((0, 0));
-        new_chunk
+        }
     }
 
     pub fn write_chunk(&mut self, byte: u8, line: usize) {
-        self.code.push(byte);
-        if self.lines_mapping_vector.last().unwrap().1 != line {
+        // A new run starts whenever the line changes, consecutive bytes from the same line share one entry
+        if self.lines_mapping_vector.last().map(|&(_, last_line)| last_line) != Some(line) {
             self.lines_mapping_vector.push((self.code.len(), line));
         }
+        self.code.push(byte);
     }
 
+    // The byte belongs to the last run that starts at or before it. Offsets past the end of the code get the
+    // line of the last byte, and 0 means the chunk is empty
     fn get_line(&self, offset: usize) -> usize {
-        return match self.lines_mapping_vector.iter().position(|&x| offset < x.0) {
-            None => self.lines_mapping_vector.last().unwrap().1,
-            Some(position) => {
-                if position == 0 {
-                    0
-                } else {
-                    self.lines_mapping_vector[position - 1].1
+        match self.lines_mapping_vector.partition_point(|&(start, _)| start <= offset) {
+            0 => 0,
+            runs => self.lines_mapping_vector[runs - 1].1,
+        }
+    }
+
+    // Offset the next byte written will get, jumps are measured from it
+    pub fn code_len(&self) -> usize {
+        self.code.len()
+    }
+
+    // Overwrite a byte that was already written, how the compiler fills in a jump once it knows how far it goes
+    pub fn patch(&mut self, offset: usize, byte: u8) {
+        self.code[offset] = byte;
+    }
+
+    // A constant that is already in the pool is reused instead of being added again. Past 256 constants the
+    // index no longer fits in CONSTANT's operand, the compiler uses CONSTANT_LONG for those
+    pub fn add_constant(&mut self, constant: Constant) -> usize {
+        if let Some(index) = self.constant_pool.iter().position(|existing| *existing == constant) {
+            return index;
+        }
+        self.constant_pool.push(constant);
+        self.constant_pool.len() - 1
+    }
+
+    pub fn constants(&self) -> &[Constant] {
+        &self.constant_pool
+    }
+
+    // Binary form of the chunk that from_bytes reads back: a magic number and format version, then the code, the
+    // line mapping and the constant pool, each prefixed with its length. Numbers are big endian, and a function
+    // constant is followed by its own chunk in the same form, without the magic number and version
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(CHUNK_MAGIC);
+        bytes.push(CHUNK_VERSION);
+        self.write_body(&mut bytes);
+        bytes
+    }
+
+    fn write_body(&self, bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(&(self.code.len() as u64).to_be_bytes());
+        bytes.extend_from_slice(&self.code);
+
+        bytes.extend_from_slice(&(self.lines_mapping_vector.len() as u64).to_be_bytes());
+        for (offset, line) in &self.lines_mapping_vector {
+            bytes.extend_from_slice(&(*offset as u64).to_be_bytes());
+            bytes.extend_from_slice(&(*line as u64).to_be_bytes());
+        }
+
+        bytes.extend_from_slice(&(self.constant_pool.len() as u64).to_be_bytes());
+        for constant in &self.constant_pool {
+            match constant {
+                Constant::Integer(val) => {
+                    bytes.push(0);
+                    bytes.extend_from_slice(&val.to_be_bytes());
+                }
+                Constant::Float(val) => {
+                    bytes.push(1);
+                    bytes.extend_from_slice(&val.to_bits().to_be_bytes());
                 }
+                Constant::Bool(val) => bytes.extend_from_slice(&[2, *val as u8]),
+                Constant::Nil => bytes.push(3),
+                Constant::Function(function) => function.write(bytes),
+                // Closures only exist while the VM runs, the compiler never puts one in the pool
+                Constant::Closure(closure) => closure.function.write(bytes),
             }
-        };
+        }
     }
 
-    pub fn add_constant(&mut self, constant: Constant) -> u8 {
-        self.constant_pool.push(constant);
-        (self.constant_pool.len() - 1).try_into().unwrap()
+    // The code itself isn't checked, a chunk with broken bytecode is caught by the VM when it runs
+    pub fn from_bytes(bytes: &[u8]) -> std::result::Result<Chunk, ChunkDecodeError> {
+        let mut reader = ChunkReader { bytes, position: 0 };
+        if reader.take(CHUNK_MAGIC.len())? != CHUNK_MAGIC {
+            return Err(decode_error("not a Wolff chunk"));
+        }
+        let version = reader.u8()?;
+        if version != CHUNK_VERSION {
+            return Err(decode_error(&format!("format version {} is not supported", version)));
+        }
+
+        let chunk = Chunk::read_body(&mut reader)?;
+        if reader.position != bytes.len() {
+            return Err(decode_error("unexpected bytes after the constant pool"));
+        }
+        Ok(chunk)
     }
 
-    pub fn disassemble_chunk(&mut self, name: &str) {
-        println!("== {} ==", name);
+    fn read_body(reader: &mut ChunkReader) -> std::result::Result<Chunk, ChunkDecodeError> {
+        let length = reader.length()?;
+        let code = reader.take(length)?.to_vec();
+
+        let length = reader.length()?;
+        let mut lines_mapping_vector = Vec::new();
+        for _ in 0..length {
+            lines_mapping_vector.push((reader.length()?, reader.length()?));
+        }
+        // get_line searches the runs by their start, so they have to be in order
+        if lines_mapping_vector.windows(2).any(|runs| runs[0].0 > runs[1].0) {
+            return Err(decode_error("the line mapping is out of order"));
+        }
+
+        let length = reader.length()?;
+        let mut constant_pool = Vec::new();
+        for _ in 0..length {
+            let constant = match reader.u8()? {
+                0 => Constant::Integer(i32::from_be_bytes(reader.array()?)),
+                1 => Constant::Float(f64::from_bits(u64::from_be_bytes(reader.array()?))),
+                2 => Constant::Bool(reader.u8()? != 0),
+                3 => Constant::Nil,
+                4 => {
+                    let name = match reader.u8()? {
+                        0 => None,
+                        _ => {
+                            let length = reader.length()?;
+                            let name = reader.take(length)?.to_vec();
+                            Some(String::from_utf8(name).map_err(|_| decode_error("a function name isn't UTF-8"))?)
+                        }
+                    };
+                    let arity = reader.length()?;
+                    let upvalue_count = reader.length()?;
+                    let chunk = Chunk::read_body(reader)?;
+                    Constant::Function(Rc::new(CompiledFunction { name, arity, upvalue_count, chunk }))
+                }
+                tag => return Err(decode_error(&format!("unknown constant type {}", tag))),
+            };
+            constant_pool.push(constant);
+        }
+
+        Ok(Chunk {
+            code,
+            lines_mapping_vector,
+            constant_pool,
+        })
+    }
+
+    pub fn disassemble_chunk(&self, name: &str) {
+        print!("{}", self.disassemble_to_string(name));
+    }
+
+    // Same listing disassemble_chunk prints, one instruction per line with its offset and source line. The chunks of
+    // the functions in the constant pool are listed after it, each under the function's name
+    pub fn disassemble_to_string(&self, name: &str) -> String {
+        let mut output = format!("== {} ==\n", name);
         let mut offset = 0;
-        loop {
+        while offset < self.code.len() {
             let (current_instruction, new_offset) = self.disassemble_instruction(offset);
 
             // Get source code line number that generated this bytecode sequence
             let source_code_line = self.get_line(offset);
 
-            println!(
-                "{:04}\t{}\t\t@{}",
-                offset, current_instruction, source_code_line
-            );
-
+            output.push_str(&format!("{:04}\t{}\t\t@{}\n", offset, current_instruction, source_code_line));
             offset += new_offset;
-            if offset == self.code.len() {
-                break;
+        }
+        output.push_str("=========\n");
+
+        for constant in &self.constant_pool {
+            if let Constant::Function(function) = constant {
+                output.push_str(&function.chunk.disassemble_to_string(&function.to_string()));
             }
         }
-        println!("=========");
+        output
     }
 
     fn disassemble_instruction(&self, offset: usize) -> (String, usize) {
-        match num::FromPrimitive::from_u8(self.code[offset]) {
+        match from_u8_to_op(self.code[offset]) {
             None => ("Unknown operation byte".to_string(), 1),
             Some(OpCode::Return) => (OpCode::Return.to_string(), 1),
-            Some(OpCode::Constant) => (self.constant_instruction(offset), 2),
+            Some(OpCode::Constant) => match self.code.get(offset + 1) {
+                Some(index) => (self.constant_instruction("CONST", *index as usize), 2),
+                None => (format!("{} <missing operand>", OpCode::Constant), 1),
+            },
+            Some(OpCode::ConstantLong) => match self.code.get(offset + 1..offset + 4) {
+                Some(&[high, middle, low]) => {
+                    (self.constant_instruction("CONST_LONG", u32::from_be_bytes([0, high, middle, low]) as usize), 4)
+                }
+                _ => (format!("{} <missing operand>", OpCode::ConstantLong), 1),
+            },
             Some(OpCode::Negate) => (OpCode::Negate.to_string(), 1),
             Some(OpCode::Addition) => (OpCode::Addition.to_string(), 1),
             Some(OpCode::Subtraction) => (OpCode::Subtraction.to_string(), 1),
             Some(OpCode::Multiplication) => (OpCode::Multiplication.to_string(), 1),
             Some(OpCode::Division) => (OpCode::Division.to_string(), 1),
+            Some(OpCode::Modulo) => (OpCode::Modulo.to_string(), 1),
+            Some(OpCode::Print) => match self.code.get(offset + 1) {
+                Some(count) => (format!("{} {}", OpCode::Print, count), 2),
+                None => (format!("{} <missing operand>", OpCode::Print), 1),
+            },
+            Some(OpCode::Pop) => (OpCode::Pop.to_string(), 1),
+            Some(op @ (OpCode::GetLocal | OpCode::SetLocal | OpCode::GetUpvalue | OpCode::SetUpvalue)) => {
+                self.byte_instruction(op, offset)
+            }
+            Some(OpCode::Call) => self.byte_instruction(OpCode::Call, offset),
+            Some(OpCode::CloseUpvalue) => (OpCode::CloseUpvalue.to_string(), 1),
+            Some(OpCode::Closure) => self.disassemble_closure(offset),
+            Some(op @ (OpCode::Equal | OpCode::Greater | OpCode::Less | OpCode::Not)) => (op.to_string(), 1),
+            Some(op @ (OpCode::True | OpCode::False | OpCode::Nil)) => (op.to_string(), 1),
+            Some(op @ (OpCode::Jump | OpCode::JumpIfFalse | OpCode::Loop)) => match self.code.get(offset + 1..offset + 3) {
+                Some(&[high, low]) => {
+                    let distance = u16::from_be_bytes([high, low]) as usize;
+                    let target = match op {
+                        OpCode::Loop => (offset + 3).wrapping_sub(distance),
+                        _ => offset + 3 + distance,
+                    };
+                    (format!("{} {:04} -> {:04}", op, offset, target), 3)
+                }
+                _ => (format!("{} <missing operand>", op), 1),
+            },
         }
     }
 
-    fn constant_instruction(&self, offset: usize) -> String {
-        let constant = &self.constant_pool[self.code[offset + 1] as usize];
-        return match constant {
-            Constant::Integer(val) => format!("CONST INT {}", val),
-            Constant::Float(val) => format!("CONST FLOAT {}", val),
+    fn byte_instruction(&self, op: OpCode, offset: usize) -> (String, usize) {
+        match self.code.get(offset + 1) {
+            Some(operand) => (format!("{} {}", op, operand), 2),
+            None => (format!("{} <missing operand>", op), 1),
+        }
+    }
+
+    // CLOSURE is followed by the index of its function and then a pair of bytes for every variable it captures:
+    // whether it is a local of the enclosing function, and its slot there or the index of the enclosing upvalue
+    fn disassemble_closure(&self, offset: usize) -> (String, usize) {
+        let index = match self.code.get(offset + 1) {
+            Some(index) => *index as usize,
+            None => return (format!("{} <missing operand>", OpCode::Closure), 1),
+        };
+        let mut text = self.constant_instruction("CLOSURE", index);
+        let upvalue_count = match self.constant_pool.get(index) {
+            Some(Constant::Function(function)) => function.upvalue_count,
+            _ => 0,
         };
+        for pair in 0..upvalue_count {
+            match self.code.get(offset + 2 + pair * 2..offset + 4 + pair * 2) {
+                Some(&[1, slot]) => text.push_str(&format!(" (local {})", slot)),
+                Some(&[_, index]) => text.push_str(&format!(" (upvalue {})", index)),
+                _ => return (format!("{} <missing captures>", text), 2),
+            }
+        }
+        (text, 2 + upvalue_count * 2)
+    }
+
+    fn constant_instruction(&self, mnemonic: &str, index: usize) -> String {
+        match self.constant_pool.get(index) {
+            None => format!("{} <no constant at {}>", mnemonic, index),
+            Some(Constant::Integer(val)) => format!("{} INT {}", mnemonic, val),
+            Some(Constant::Float(val)) => format!("{} FLOAT {}", mnemonic, val),
+            Some(Constant::Bool(val)) => format!("{} BOOL {}", mnemonic, val),
+            Some(Constant::Nil) => format!("{} NIL", mnemonic),
+            Some(Constant::Function(function)) => format!("{} FUN {}", mnemonic, function),
+            Some(Constant::Closure(closure)) => format!("{} FUN {}", mnemonic, closure.function),
+        }
+    }
+}
+
+const CHUNK_MAGIC: &[u8] = b"WOLF";
+// Goes up whenever the binary form of a chunk changes, older files are then refused instead of misread
+const CHUNK_VERSION: u8 = 2;
+
+fn decode_error(message: &str) -> ChunkDecodeError {
+    ChunkDecodeError {
+        message: message.to_string(),
+    }
+}
+
+// Reads the fields of a serialized chunk one after the other, running out of bytes is an error
+struct ChunkReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> ChunkReader<'a> {
+    fn take(&mut self, count: usize) -> std::result::Result<&'a [u8], ChunkDecodeError> {
+        let end = self.position.checked_add(count).filter(|end| *end <= self.bytes.len());
+        match end {
+            Some(end) => {
+                let taken = &self.bytes[self.position..end];
+                self.position = end;
+                Ok(taken)
+            }
+            None => Err(decode_error("the data ends too early")),
+        }
+    }
+
+    fn array<const N: usize>(&mut self) -> std::result::Result<[u8; N], ChunkDecodeError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    fn u8(&mut self) -> std::result::Result<u8, ChunkDecodeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn length(&mut self) -> std::result::Result<usize, ChunkDecodeError> {
+        usize::try_from(u64::from_be_bytes(self.array()?)).map_err(|_| decode_error("a length is too big"))
     }
 }
 
 impl VM {
     pub fn new(debug: bool, stacktrace: bool) -> Self {
-        VM {
-            chunk: Chunk::new(),
-            ip: 0,
-            debug,
-            stacktrace,
-            stack: Vec::new(),
-            //globals: Vec::new(),
-            //frames: Vec::new(),
-        }
+        Self::from_chunk(&Chunk::new(), debug, stacktrace)
     }
 
     pub fn from_chunk(chunk: &Chunk, debug: bool, stacktrace: bool) -> Self {
         VM {
             chunk: chunk.clone(),
-            ip: 0,
             debug,
             stacktrace,
             stack: Vec::new(),
             //globals: Vec::new(),
-            //frames: Vec::new(),
+            frames: Vec::new(),
+            open_upvalues: Vec::new(),
+            output: Box::new(io::stdout()),
         }
     }
 
-    pub fn interpret(&mut self) -> u32 {
-        self.ip = 0;
-        return self.run();
+    // Send what PRINT writes somewhere other than stdout, e.g. a buffer that a test reads back afterwards
+    pub fn set_output(&mut self, output: Box<dyn Write>) {
+        self.output = output;
     }
 
-    fn run(&mut self) -> u32 {
-        // Run as long as there is code to run
-        while self.ip != self.chunk.code.len() {
-            // Current instruction is the byte at which self.ip points in the chunk being executed
-            let curr_instruction = self.chunk.code[self.ip];
+    // Run the chunk from the start, the result is whatever is on top of the stack when it returns
+    pub fn interpret(&mut self) -> std::result::Result<Option<Constant>, VMRuntimeError> {
+        // The chunk runs as a function without parameters, its variables start at the bottom of the stack
+        let script = CompiledFunction {
+            name: None,
+            arity: 0,
+            upvalue_count: 0,
+            chunk: self.chunk.clone(),
+        };
+        self.stack.clear();
+        self.open_upvalues.clear();
+        self.frames = vec![CallFrame {
+            closure: Rc::new(Closure { function: Rc::new(script), upvalues: Vec::new() }),
+            ip: 0,
+            base: 0,
+        }];
+        self.run()
+    }
+
+    fn frame(&self) -> &CallFrame {
+        &self.frames[self.frames.len() - 1]
+    }
+
+    fn frame_mut(&mut self) -> &mut CallFrame {
+        let last = self.frames.len() - 1;
+        &mut self.frames[last]
+    }
+
+    // Chunk of the function running right now
+    fn code(&self) -> &Chunk {
+        &self.frame().closure.function.chunk
+    }
+
+    fn runtime_error(&self, message: String) -> VMRuntimeError {
+        let (line, offset) = match self.frames.last() {
+            Some(frame) => (frame.closure.function.chunk.get_line(frame.ip), frame.ip),
+            None => (0, 0),
+        };
+        VMRuntimeError { message, line, offset }
+    }
+
+    // A well formed chunk never pops more than it pushed, so this only fails for broken bytecode
+    fn pop(&mut self) -> std::result::Result<Constant, VMRuntimeError> {
+        match self.stack.pop() {
+            Some(constant) => Ok(constant),
+            None => Err(self.runtime_error("Stack underflow".to_string())),
+        }
+    }
+
+    fn peek(&self) -> std::result::Result<Constant, VMRuntimeError> {
+        match self.stack.last() {
+            Some(constant) => Ok(constant.clone()),
+            None => Err(self.runtime_error("Stack underflow".to_string())),
+        }
+    }
+
+    // The byte after the current instruction, a chunk that ends before it is cut short
+    fn read_operand(&self) -> std::result::Result<u8, VMRuntimeError> {
+        match self.code().code.get(self.frame().ip + 1) {
+            Some(operand) => Ok(*operand),
+            None => Err(self.runtime_error("Instruction is missing its operand".to_string())),
+        }
+    }
+
+    fn push_constant(&mut self, index: usize) -> std::result::Result<(), VMRuntimeError> {
+        match self.code().constant_pool.get(index) {
+            Some(constant) => {
+                self.stack.push(constant.clone());
+                Ok(())
+            }
+            None => Err(self.runtime_error(format!("There is no constant at index {}", index))),
+        }
+    }
+
+    // Jump distances are two bytes after the instruction, the high byte first
+    fn read_jump(&self) -> std::result::Result<usize, VMRuntimeError> {
+        let ip = self.frame().ip;
+        match self.code().code.get(ip + 1..ip + 3) {
+            Some(&[high, low]) => Ok(u16::from_be_bytes([high, low]) as usize),
+            _ => Err(self.runtime_error("Jump is missing its distance".to_string())),
+        }
+    }
+
+    // Stack position of a slot of the current frame, broken bytecode can point past the top of the stack
+    fn slot(&self, slot: u8) -> std::result::Result<usize, VMRuntimeError> {
+        let position = self.frame().base + slot as usize;
+        match position < self.stack.len() {
+            true => Ok(position),
+            false => Err(self.runtime_error(format!("There is no local in slot {}", slot))),
+        }
+    }
+
+    fn upvalue(&self, index: u8) -> std::result::Result<Rc<RefCell<Upvalue>>, VMRuntimeError> {
+        match self.frame().closure.upvalues.get(index as usize) {
+            Some(upvalue) => Ok(Rc::clone(upvalue)),
+            None => Err(self.runtime_error(format!("There is no upvalue at index {}", index))),
+        }
+    }
+
+    fn binary_op(
+        &mut self,
+        op: fn(Constant, Constant) -> std::result::Result<Constant, String>,
+    ) -> std::result::Result<(), VMRuntimeError> {
+        let b = self.pop()?;
+        let a = self.pop()?;
+        match op(a, b) {
+            Ok(constant) => {
+                self.stack.push(constant);
+                Ok(())
+            }
+            Err(message) => Err(self.runtime_error(message)),
+        }
+    }
+
+    // The callee is below its arguments, it becomes slot 0 of the new frame
+    fn call(&mut self, argument_count: usize) -> std::result::Result<(), VMRuntimeError> {
+        let base = match self.stack.len().checked_sub(argument_count + 1) {
+            Some(base) => base,
+            None => return Err(self.runtime_error("Stack underflow".to_string())),
+        };
+        let closure = match &self.stack[base] {
+            Constant::Closure(closure) => Rc::clone(closure),
+            _ => return Err(self.runtime_error("Can only call functions".to_string())),
+        };
+        if argument_count != closure.function.arity {
+            let message = format!("Expected {} arguments but got {}", closure.function.arity, argument_count);
+            return Err(self.runtime_error(message));
+        }
+        if self.frames.len() == MAX_FRAMES {
+            return Err(self.runtime_error("Stack overflow".to_string()));
+        }
+
+        self.frames.push(CallFrame { closure, ip: 0, base });
+        Ok(())
+    }
+
+    // Two closures that capture the same variable get the same upvalue, so they see each other's assignments
+    fn capture_upvalue(&mut self, position: usize) -> Rc<RefCell<Upvalue>> {
+        let open = self.open_upvalues.iter().find(|upvalue| {
+            matches!(*upvalue.borrow(), Upvalue::Open(slot) if slot == position)
+        });
+        if let Some(upvalue) = open {
+            return Rc::clone(upvalue);
+        }
+
+        let upvalue = Rc::new(RefCell::new(Upvalue::Open(position)));
+        self.open_upvalues.push(Rc::clone(&upvalue));
+        upvalue
+    }
+
+    // Move the values of the slots from `position` up into their upvalues, they are about to leave the stack
+    fn close_upvalues(&mut self, position: usize) {
+        let stack = &self.stack;
+        self.open_upvalues.retain(|upvalue| {
+            let slot = match *upvalue.borrow() {
+                Upvalue::Open(slot) if slot >= position => slot,
+                _ => return true,
+            };
+            *upvalue.borrow_mut() = Upvalue::Closed(stack[slot].clone());
+            false
+        });
+    }
+
+    // CLOSURE's operand is the function in the constant pool, then a pair of bytes for each variable it captures
+    fn make_closure(&mut self) -> std::result::Result<usize, VMRuntimeError> {
+        let index = self.read_operand()? as usize;
+        let function = match self.code().constant_pool.get(index) {
+            Some(Constant::Function(function)) => Rc::clone(function),
+            _ => return Err(self.runtime_error(format!("There is no function at index {}", index))),
+        };
+
+        let ip = self.frame().ip;
+        let mut upvalues = Vec::with_capacity(function.upvalue_count);
+        for pair in 0..function.upvalue_count {
+            let upvalue = match self.code().code.get(ip + 2 + pair * 2..ip + 4 + pair * 2) {
+                Some(&[1, slot]) => {
+                    let position = self.slot(slot)?;
+                    self.capture_upvalue(position)
+                }
+                Some(&[_, index]) => self.upvalue(index)?,
+                _ => return Err(self.runtime_error("Closure is missing its captures".to_string())),
+            };
+            upvalues.push(upvalue);
+        }
+
+        let size = 2 + function.upvalue_count * 2;
+        self.stack.push(Constant::Closure(Rc::new(Closure { function, upvalues })));
+        Ok(size)
+    }
+
+    fn run(&mut self) -> std::result::Result<Option<Constant>, VMRuntimeError> {
+        loop {
+            let ip = self.frame().ip;
+            // Running off the end of the chunk without a Return still counts as success, just without a result.
+            // Compiled functions always end with one
+            let curr_instruction = match self.code().code.get(ip) {
+                Some(byte) => *byte,
+                None if self.frames.len() == 1 => return Ok(None),
+                None => return Err(self.runtime_error("Function ends without returning".to_string())),
+            };
 
             // If debug is on, disassemble the current instruction and print it
             if self.debug {
                 println!(
                     "{:04}\t{}",
-                    self.ip,
-                    self.chunk.disassemble_instruction(self.ip).0
+                    ip,
+                    self.code().disassemble_instruction(ip).0
                 );
             }
 
             // Match the current byte to an OpCode, if it doesn't match, spit out an error, else execute the instruction
-            let instruction_op = from_u8_to_op(curr_instruction);
-            if instruction_op.is_none() {
-                return 0;
-            }
+            let instruction_op = match from_u8_to_op(curr_instruction) {
+                Some(op) => op,
+                None => return Err(self.runtime_error(format!("Unknown operation byte {}", curr_instruction))),
+            };
 
-            let a = instruction_op.unwrap();
-            
-            let ip_offset = match instruction_op.unwrap() {
-                ReturnOp => {
-                    return 0;
+            let ip_offset = match instruction_op {
+                // The variables of the function leave the stack with it, the ones that were captured move into
+                // their upvalues first
+                OpCode::Return => {
+                    let result = self.pop()?;
+                    let base = self.frame().base;
+                    self.close_upvalues(base);
+                    self.frames.pop();
+                    if self.frames.is_empty() {
+                        return Ok(Some(result));
+                    }
+                    self.stack.truncate(base);
+                    self.stack.push(result);
+                    0
                 }
-                ConstantOp => {
-                    let constant = self.chunk.constant_pool[self.chunk.code[self.ip + 1] as usize];
-                    self.stack.push(constant);
+                OpCode::Constant => {
+                    let index = self.read_operand()?;
+                    self.push_constant(index as usize)?;
                     2
                 }
-                NegOp => {
-                    let constant = -self.stack.pop().unwrap();
+                OpCode::ConstantLong => {
+                    let index = match self.code().code.get(ip + 1..ip + 4) {
+                        Some(&[high, middle, low]) => u32::from_be_bytes([0, high, middle, low]),
+                        _ => return Err(self.runtime_error("Instruction is missing its operand".to_string())),
+                    };
+                    self.push_constant(index as usize)?;
+                    4
+                }
+                OpCode::Negate => {
+                    let constant = match -self.pop()? {
+                        Ok(constant) => constant,
+                        Err(message) => return Err(self.runtime_error(message)),
+                    };
                     self.stack.push(constant);
                     1
                 }
-                AddOp => {
-                    let b = self.stack.pop().unwrap();
-                    let a = self.stack.pop().unwrap();
-                    self.stack.push(a + b);
+                // Like JumpIfFalse, nil and false are falsey and everything else is truthy
+                OpCode::Not => {
+                    let value = self.pop()?;
+                    self.stack.push(Constant::Bool(value.is_falsey()));
+                    1
+                }
+                OpCode::Equal => {
+                    self.binary_op(|a, b| Ok(Constant::Bool(a.equals(&b))))?;
+                    1
+                }
+                OpCode::Greater => {
+                    self.binary_op(|a, b| a.compare(&b, |val_1, val_2| val_1 > val_2))?;
+                    1
+                }
+                OpCode::Less => {
+                    self.binary_op(|a, b| a.compare(&b, |val_1, val_2| val_1 < val_2))?;
+                    1
+                }
+                OpCode::True => {
+                    self.stack.push(Constant::Bool(true));
+                    1
+                }
+                OpCode::False => {
+                    self.stack.push(Constant::Bool(false));
+                    1
+                }
+                OpCode::Nil => {
+                    self.stack.push(Constant::Nil);
+                    1
+                }
+                // Jumps set ip themselves, the distance is counted from the end of the jump instruction
+                OpCode::Jump => {
+                    self.frame_mut().ip += 3 + self.read_jump()?;
+                    0
+                }
+                OpCode::JumpIfFalse => {
+                    let distance = self.read_jump()?;
+                    let condition = self.peek()?;
+                    self.frame_mut().ip += 3 + if condition.is_falsey() { distance } else { 0 };
+                    0
+                }
+                OpCode::Loop => {
+                    let distance = self.read_jump()?;
+                    self.frame_mut().ip = match (ip + 3).checked_sub(distance) {
+                        Some(target) => target,
+                        None => return Err(self.runtime_error("Loop jumps back before the start of the chunk".to_string())),
+                    };
+                    0
+                }
+                OpCode::Addition => {
+                    self.binary_op(Add::add)?;
+                    1
+                }
+                OpCode::Subtraction => {
+                    self.binary_op(Sub::sub)?;
+                    1
+                }
+                OpCode::Multiplication => {
+                    self.binary_op(Mul::mul)?;
                     1
                 }
-                SubOp => {
-                    let b = self.stack.pop().unwrap();
-                    let a = self.stack.pop().unwrap();
-                    self.stack.push(a - b);
+                OpCode::Division => {
+                    self.binary_op(Div::div)?;
                     1
                 }
-                MulOp => {
-                    let b = self.stack.pop().unwrap();
-                    let a = self.stack.pop().unwrap();
-                    self.stack.push(a * b);
+                OpCode::Modulo => {
+                    self.binary_op(Rem::rem)?;
                     1
                 }
-                DivOp => {
-                    let b = self.stack.pop().unwrap();
-                    let a = self.stack.pop().unwrap();
-                    self.stack.push(a / b);
+                OpCode::Print => {
+                    let count = self.read_operand()? as usize;
+                    if count > self.stack.len() {
+                        return Err(self.runtime_error("Stack underflow".to_string()));
+                    }
+                    let values: Vec<String> = self.stack.split_off(self.stack.len() - count).iter().map(Constant::to_string).collect();
+                    if let Err(e) = writeln!(self.output, "{}", values.join(" ")) {
+                        return Err(self.runtime_error(format!("Can't write the output: {}", e)));
+                    }
+                    2
+                }
+                OpCode::Pop => {
+                    self.pop()?;
+                    1
+                }
+                OpCode::GetLocal => {
+                    let position = self.slot(self.read_operand()?)?;
+                    self.stack.push(self.stack[position].clone());
+                    2
+                }
+                // Assignments are expressions, the value stays on the stack
+                OpCode::SetLocal => {
+                    let position = self.slot(self.read_operand()?)?;
+                    self.stack[position] = self.peek()?;
+                    2
+                }
+                OpCode::GetUpvalue => {
+                    let upvalue = self.upvalue(self.read_operand()?)?;
+                    let value = match &*upvalue.borrow() {
+                        Upvalue::Open(slot) => self.stack[*slot].clone(),
+                        Upvalue::Closed(value) => value.clone(),
+                    };
+                    self.stack.push(value);
+                    2
+                }
+                OpCode::SetUpvalue => {
+                    let upvalue = self.upvalue(self.read_operand()?)?;
+                    let value = self.peek()?;
+                    match &mut *upvalue.borrow_mut() {
+                        Upvalue::Open(slot) => self.stack[*slot] = value,
+                        Upvalue::Closed(closed) => *closed = value,
+                    }
+                    2
+                }
+                OpCode::Closure => self.make_closure()?,
+                OpCode::CloseUpvalue => {
+                    self.close_upvalues(self.stack.len().saturating_sub(1));
+                    self.pop()?;
                     1
                 }
+                // The caller carries on after the call once the callee returns, so its ip moves on before the new
+                // frame starts
+                OpCode::Call => {
+                    let argument_count = self.read_operand()? as usize;
+                    self.frame_mut().ip += 2;
+                    self.call(argument_count)?;
+                    0
+                }
             };
 
             // If stacktrace is true, print the stack after every instruction as well
@@ -299,142 +997,188 @@ impl VM {
                 println!("\n-------");
             }
 
-            self.ip += ip_offset;
+            self.frame_mut().ip += ip_offset;
         }
-
-        // If the loop runs zero times, return 0 because technically it executed succesfully
-        return 0;
     }
 }
 
-fn from_u8_to_op<T: OpCode>(op_byte: u8) -> Option<T> {
-    match op_byte {
-        0 => Option::Some(ReturnOp),
-        1 => Option::Some(ConstantOp),
-        2 => Option::Some(NegOp),
-        3 => Option::Some(AddOp),
-        4 => Option::Some(SubOp),
-        5 => Option::Some(MulOp),
-        6 => Option::Some(DivOp),
-        _ => None,
-    }
+fn from_u8_to_op(op_byte: u8) -> Option<OpCode> {
+    num::FromPrimitive::from_u8(op_byte)
 }
 
-// Define OpCode trait
-trait OpCode: Display {
-    fn number_of_bytes(&self) -> usize {
-        1
-    }
-
-    fn run(&self, _: &mut Vec<Constant>, _: &mut Vec<Constant>) -> usize {
-        self.number_of_bytes()
-    }
+// OpCode enum that holds every instruction the VM knows about, the discriminant is the byte written in the chunk
+#[derive(FromPrimitive, Clone, Copy, Debug, PartialEq)]
+pub enum OpCode {
+    // Return operation - pops the result and leaves the function, the caller gets the result on top of its stack.
+    // Returning from the chunk itself ends the program with that result
+    Return = 0,
+    // Constant operation - loads a constant (either an int, float) from the constant section onto the stack
+    Constant = 1,
+    // Negate operation - pops the stack, negates the element and pushes it back
+    Negate = 2,
+    // Addition operation - adds the last two elements on the stack
+    Addition = 3,
+    // Subtraction operation - subtracts the last two elements on the stack
+    Subtraction = 4,
+    // Multiplication operation - multiplies the last two elements on the stack
+    Multiplication = 5,
+    // Division operation - divides the last two elements on the stack
+    Division = 6,
+    // Print operation - pops as many values as its operand says and prints them on one line, first pushed first
+    Print = 7,
+    // Pop operation - throws away the value on top of the stack, what an expression statement leaves behind
+    Pop = 8,
+    // Equal operation - pops two values and pushes whether they are equal
+    Equal = 9,
+    // Greater operation - pops two numbers and pushes whether the first one pushed is greater
+    Greater = 10,
+    // Less operation - pops two numbers and pushes whether the first one pushed is smaller
+    Less = 11,
+    // Not operation - pops a value and pushes true if it is falsey (nil or false), false if it is truthy
+    Not = 12,
+    // True, False and Nil operations - push that value without going through the constant pool
+    True = 13,
+    False = 14,
+    Nil = 15,
+    // Jump operation - moves ip forward by the distance in the next two bytes
+    Jump = 16,
+    // JumpIfFalse operation - jumps forward like Jump if the value on top of the stack is falsey, without popping it
+    JumpIfFalse = 17,
+    // Loop operation - moves ip back by the distance in the next two bytes
+    Loop = 18,
+    // ConstantLong operation - like Constant, for the indexes that need the next three bytes, high byte first
+    ConstantLong = 19,
+    // Modulo operation - pops two numbers and pushes the remainder of dividing the first one pushed by the other
+    Modulo = 20,
+    // GetLocal and SetLocal operations - read or assign the slot of the current frame in the next byte
+    GetLocal = 21,
+    SetLocal = 22,
+    // GetUpvalue and SetUpvalue operations - read or assign a variable the running closure captured, the next byte
+    // is its index among the closure's upvalues
+    GetUpvalue = 23,
+    SetUpvalue = 24,
+    // Closure operation - pushes a closure of the function in the constant pool at the next byte, a pair of bytes
+    // follows for every variable it captures (whether it is a local of this frame, and its slot or upvalue index)
+    Closure = 25,
+    // CloseUpvalue operation - pops a captured variable at the end of its scope, moving its value into its upvalue
+    CloseUpvalue = 26,
+    // Call operation - calls the value below as many arguments as the next byte says
+    Call = 27,
 }
 
-// Return operation - for now, just halts the program
-struct ReturnOp;
-impl OpCode for ReturnOp {}
-
-impl Display for ReturnOp {
+impl Display for OpCode {
     fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
-        write!(f, "{}", "RETURN")
+        let mnemonic = match self {
+            OpCode::Return => "RETURN",
+            OpCode::Constant => "CONSTANT",
+            OpCode::ConstantLong => "CONSTANT_LONG",
+            OpCode::Negate => "NEG",
+            OpCode::Addition => "ADD",
+            OpCode::Subtraction => "SUB",
+            OpCode::Multiplication => "MUL",
+            OpCode::Division => "DIV",
+            OpCode::Modulo => "MOD",
+            OpCode::Print => "PRINT",
+            OpCode::Pop => "POP",
+            OpCode::Equal => "EQUAL",
+            OpCode::Greater => "GREATER",
+            OpCode::Less => "LESS",
+            OpCode::Not => "NOT",
+            OpCode::True => "TRUE",
+            OpCode::False => "FALSE",
+            OpCode::Nil => "NIL",
+            OpCode::Jump => "JUMP",
+            OpCode::JumpIfFalse => "JUMP_IF_FALSE",
+            OpCode::Loop => "LOOP",
+            OpCode::GetLocal => "GET_LOCAL",
+            OpCode::SetLocal => "SET_LOCAL",
+            OpCode::GetUpvalue => "GET_UPVALUE",
+            OpCode::SetUpvalue => "SET_UPVALUE",
+            OpCode::Closure => "CLOSURE",
+            OpCode::CloseUpvalue => "CLOSE_UPVALUE",
+            OpCode::Call => "CALL",
+        };
+        write!(f, "{}", mnemonic)
     }
 }
 
-// Constant operation - loads a constant (either an int, float) from the constant section onto the stack
-struct ConstantOp;
-impl OpCode for ConstantOp {
-    fn number_of_bytes(&self) -> usize {
-        2
-    }
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-impl Display for ConstantOp {
-    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
-        write!(f, "{}", "CONSTANT")
+    // Every byte comes from line 1, for tests that only care about what the bytecode does
+    fn chunk_of(code: &[u8], constants: &[Constant]) -> Chunk {
+        let mut chunk = Chunk::new();
+        for constant in constants {
+            chunk.add_constant(constant.clone());
+        }
+        for byte in code {
+            chunk.write_chunk(*byte, 1);
+        }
+        chunk
     }
-}
 
-// Negate operation - pops the stack, negates the element and pushes it back
-struct NegOp;
-impl OpCode for NegOp {
-    fn run(&self, stack: &mut Vec<Constant>, _: &mut Vec<Constant>) -> usize {
-        let a = -stack.pop().unwrap();
-        stack.push(a);
-        self.number_of_bytes()
+    fn run(chunk: &Chunk) -> std::result::Result<Option<Constant>, VMRuntimeError> {
+        let mut vm = VM::from_chunk(chunk, false, false);
+        vm.set_output(Box::new(io::sink()));
+        vm.interpret()
     }
-}
 
-impl Display for NegOp {
-    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
-        write!(f, "{}", "NEG")
-    }
-}
+    #[test]
+    fn a_truncated_chunk_is_an_error_not_a_panic() {
+        let constants = [Constant::Integer(1), Constant::Integer(2)];
+        let whole = chunk_of(&[OpCode::Constant as u8, 0, OpCode::Constant as u8, 1, OpCode::Addition as u8], &constants);
+        assert_eq!(run(&whole).unwrap(), None);
 
-// Addition operation - adds the last two elements on the stack
-struct AddOp;
-impl OpCode for AddOp {
-    fn run(&self, stack: &mut Vec<Constant>, _: &mut Vec<Constant>) -> usize {
-        let b = stack.pop().unwrap();
-        let a = stack.pop().unwrap();
-        stack.push(a + b);
-        self.number_of_bytes()
-    }
-}
+        // Cut short before the operand of either CONSTANT
+        for length in [1, 3] {
+            let error = run(&chunk_of(&whole.code[..length], &constants)).unwrap_err();
+            assert_eq!(error.line, 1);
+        }
+        let error = run(&chunk_of(&[OpCode::Constant as u8, 0, OpCode::Addition as u8], &constants)).unwrap_err();
+        assert_eq!((error.message.as_str(), error.offset), ("Stack underflow", 2));
 
-impl Display for AddOp {
-    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
-        write!(f, "{}", "ADD")
-    }
-}
+        assert!(run(&chunk_of(&[OpCode::ConstantLong as u8, 0, 0], &constants)).is_err());
+        assert!(run(&chunk_of(&[OpCode::Jump as u8, 0], &[])).is_err());
+        assert!(run(&chunk_of(&[OpCode::Return as u8], &[])).is_err());
+        assert!(run(&chunk_of(&[OpCode::Constant as u8, 5], &constants)).is_err());
+        assert_eq!(run(&chunk_of(&[255], &[])).unwrap_err().message, "Unknown operation byte 255");
 
-// Subtraction operation - subtracts the last two elements on the stack
-struct SubOp;
-impl OpCode for SubOp {
-    fn run(&self, stack: &mut Vec<Constant>, _: &mut Vec<Constant>) -> usize {
-        let b = stack.pop().unwrap();
-        let a = stack.pop().unwrap();
-        stack.push(a - b);
-        self.number_of_bytes()
+        // A chunk file that was cut short doesn't even load
+        let bytes = whole.to_bytes();
+        assert!(Chunk::from_bytes(&bytes).is_ok());
+        assert!((0..bytes.len()).all(|length| Chunk::from_bytes(&bytes[..length]).is_err()));
     }
-}
 
-impl Display for SubOp {
-    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
-        write!(f, "{}", "SUB")
-    }
-}
+    #[test]
+    fn an_equal_constant_is_only_added_once() {
+        let mut chunk = Chunk::new();
+        assert_eq!(chunk.add_constant(Constant::Integer(7)), 0);
+        assert_eq!(chunk.add_constant(Constant::Integer(7)), 0);
+        assert_eq!(chunk.constants().len(), 1);
 
-// Multiplication operation - multiplies the last two elements on the stack 
-struct MulOp;
-impl OpCode for MulOp {
-    fn run(&self, stack: &mut Vec<Constant>, _: &mut Vec<Constant>) -> usize {
-        let b = stack.pop().unwrap();
-        let a = stack.pop().unwrap();
-        stack.push(a * b);
-        self.number_of_bytes()
+        // Only the same kind of constant with the same value is reused
+        assert_eq!(chunk.add_constant(Constant::Float(7.0)), 1);
+        assert_eq!(chunk.add_constant(Constant::Float(-0.0)), 2);
+        assert_eq!(chunk.add_constant(Constant::Float(0.0)), 3);
+        assert_eq!(chunk.add_constant(Constant::Float(7.0)), 1);
+        assert_eq!(chunk.constants().len(), 4);
     }
-}
 
-impl Display for MulOp {
-    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
-        write!(f, "{}", "MUL")
-    }
-}
+    #[test]
+    fn every_byte_gets_the_line_it_was_written_for() {
+        assert_eq!(Chunk::new().get_line(0), 0);
 
-// Division operation - divides the last two elements on the stack 
-struct DivOp;
-impl OpCode for DivOp {
-    fn run(&self, stack: &mut Vec<Constant>, _: &mut Vec<Constant>) -> usize {
-        let b = stack.pop().unwrap();
-        let a = stack.pop().unwrap();
-        stack.push(a / b);
-        self.number_of_bytes()
+        // Line 2 emits nothing, line 3 several bytes, and line 3 comes back after line 4 like a loop condition does
+        let lines = [1, 1, 3, 3, 3, 4, 3, 3, 10];
+        let mut chunk = Chunk::new();
+        for line in lines {
+            chunk.write_chunk(OpCode::Nil as u8, line);
+        }
+        assert_eq!(chunk.lines_mapping_vector, [(0, 1), (2, 3), (5, 4), (6, 3), (8, 10)]);
+        for (offset, line) in lines.iter().enumerate() {
+            assert_eq!(chunk.get_line(offset), *line, "offset {}", offset);
+        }
+        assert_eq!(chunk.get_line(lines.len()), 10);
     }
 }
-
-impl Display for DivOp {
-    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
-        write!(f, "{}", "DIV")
-    }
-}
\ No newline at end of file
@@ -0,0 +1,90 @@
+use crate::expressions::Expr;
+use crate::statements::Stmt;
+
+// For passes that only care about a few kinds of nodes, instead of implementing every method of ExprVisitor and
+// StmtVisitor. By default both methods go down into the children of the node, a pass overrides them, matches the
+// nodes it wants and calls walk_expr or walk_stmt to keep going down (or doesn't, to skip the children)
+pub trait Walk {
+    fn visit_expr(&mut self, expression: &Expr) {
+        walk_expr(self, expression);
+    }
+
+    fn visit_stmt(&mut self, statement: &Stmt) {
+        walk_stmt(self, statement);
+    }
+}
+
+// Visits the children of the expression in the order they appear in the source
+pub fn walk_expr<W: Walk + ?Sized>(walker: &mut W, expression: &Expr) {
+    match expression {
+        Expr::ArrayLiteral { elements, .. }
+        | Expr::Tuple { elements, .. }
+        | Expr::Interpolation { parts: elements, .. } => {
+            elements.iter().for_each(|element| walker.visit_expr(element));
+        }
+        Expr::Assign { value, .. } => walker.visit_expr(value),
+        Expr::Binary { left, right, .. } | Expr::Logical { left, right, .. } => {
+            walker.visit_expr(left);
+            walker.visit_expr(right);
+        }
+        Expr::Call { callee, arguments, .. } => {
+            walker.visit_expr(callee);
+            arguments.iter().for_each(|argument| walker.visit_expr(argument));
+        }
+        Expr::Grouping { expression } => walker.visit_expr(expression),
+        Expr::Index { target, index, .. } => {
+            walker.visit_expr(target);
+            walker.visit_expr(index);
+        }
+        Expr::IndexAssign { target, index, value, .. } => {
+            walker.visit_expr(target);
+            walker.visit_expr(index);
+            walker.visit_expr(value);
+        }
+        Expr::Lambda { body, .. } => body.iter().for_each(|statement| walker.visit_stmt(statement)),
+        Expr::MapLiteral { entries, .. } => {
+            for (key, value) in entries {
+                walker.visit_expr(key);
+                walker.visit_expr(value);
+            }
+        }
+        Expr::Ternary { condition, then_branch, else_branch, .. } => {
+            walker.visit_expr(condition);
+            walker.visit_expr(then_branch);
+            walker.visit_expr(else_branch);
+        }
+        Expr::Unary { right, .. } => walker.visit_expr(right),
+        Expr::Literal { .. } | Expr::Variable { .. } => {}
+    }
+}
+
+// Same as walk_expr, the increment of a loop comes after its body since that is when it runs
+pub fn walk_stmt<W: Walk + ?Sized>(walker: &mut W, statement: &Stmt) {
+    match statement {
+        Stmt::Block { statements } | Stmt::Function { body: statements, .. } => {
+            statements.iter().for_each(|statement| walker.visit_stmt(statement));
+        }
+        Stmt::Expression { expression } | Stmt::Var { initializer: expression, .. } => walker.visit_expr(expression),
+        Stmt::If { condition, then_branch, else_branch, .. } => {
+            walker.visit_expr(condition);
+            walker.visit_stmt(then_branch);
+            if let Some(else_branch) = else_branch {
+                walker.visit_stmt(else_branch);
+            }
+        }
+        Stmt::Print { expressions, .. } => expressions.iter().for_each(|expression| walker.visit_expr(expression)),
+        Stmt::Return { value, .. } => {
+            if let Some(value) = value {
+                walker.visit_expr(value);
+            }
+        }
+        Stmt::While { condition, body, increment, .. } => {
+            walker.visit_expr(condition);
+            walker.visit_stmt(body);
+            if let Some(increment) = increment {
+                walker.visit_expr(increment);
+            }
+        }
+        Stmt::Break { .. } | Stmt::Continue { .. } => {}
+    }
+}